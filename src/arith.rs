@@ -0,0 +1,545 @@
+use crate::vars::Vars;
+
+/// 演算子。長いものから順に並べ、最長一致で切り出す。
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", "(", ")", ",",
+];
+
+/// 優先順位の低いものから並べた二項演算子。**は右結合のため別に扱う。
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// 変数の値を式として評価する際の、再帰の深さの上限
+const MAX_DEPTH: usize = 64;
+
+/// 算術式の字句
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(String),      // 数値。0x1f、8#17のような表記も含む
+    Name(String),     // 変数名
+    Op(&'static str), // 演算子と括弧
+}
+
+/// 算術式の構文木
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),             // -、+、!、~
+    Binary(&'static str, Box<Expr>, Box<Expr>), // &&、||は短絡評価する
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),      // c ? a : b
+    Assign {
+        name: String,
+        op: Option<&'static str>, // +=なら+。単純な代入ならNone
+        value: Box<Expr>,
+    },
+    IncDec {
+        name: String,
+        delta: i64,   // ++なら1、--なら-1
+        prefix: bool, // ++iのように前置なら真
+    },
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+/// 算術式を評価。変数の参照と代入はvarsに対して行う。
+pub fn eval(expr: &str, vars: &mut Vars) -> Result<i64, String> {
+    eval_depth(expr, vars, 0)
+}
+
+/// 再帰の深さを指定して算術式を評価。
+fn eval_depth(expr: &str, vars: &mut Vars, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("{}: 式の再帰が深すぎます。", expr.trim()));
+    }
+    let error = |msg: &str| format!("{}: {}", expr.trim(), msg);
+
+    let tokens = tokenize(expr).map_err(|e| error(&e))?;
+    if tokens.is_empty() {
+        return Ok(0); // 空の式は0
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let ast = parser.parse_comma().map_err(|e| error(&e))?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(error(&format!("構文エラーです。（{}）", token_str(token))));
+    }
+
+    let mut evaluator = Evaluator { vars, depth };
+    evaluator.eval(&ast).map_err(|e| error(&e))
+}
+
+/// エラーメッセージ用のトークンの表記。
+fn token_str(token: &Token) -> &str {
+    match token {
+        Token::Num(s) | Token::Name(s) => s,
+        Token::Op(op) => op,
+    }
+}
+
+/// 算術式を字句に分割。
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            // 8#17や64#@_のような基数付きの数値も1つの字句とする
+            let start = i;
+            let num = c.is_ascii_digit();
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '_'
+                    || (num && matches!(chars[i], '#' | '@')))
+            {
+                i += 1;
+            }
+            let s: String = chars[start..i].iter().collect();
+            tokens.push(if num { Token::Num(s) } else { Token::Name(s) });
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("{}: 不正な文字です。", c))?;
+            tokens.push(Token::Op(op));
+            i += op.chars().count();
+        }
+    }
+    Ok(tokens)
+}
+
+/// 数値の表記を解釈。0x1fは16進数、017は8進数、base#nはbase進数とする。
+fn parse_number(s: &str) -> Result<i64, String> {
+    let invalid = || format!("{}: 数値として不正です。", s);
+    let (base, digits) = if let Some((base, digits)) = s.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => return Err(format!("{}: 不正な基数です。", s)),
+        }
+    } else if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        (16, hex)
+    } else if s.len() > 1 && s.starts_with('0') {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut n: i64 = 0;
+    for c in digits.chars() {
+        // 36進数までは大文字と小文字を区別せず、それより大きければa-z、A-Z、@、_の順
+        let d = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if d >= base {
+            return Err(invalid());
+        }
+        n = n.wrapping_mul(base as i64).wrapping_add(d as i64);
+    }
+    Ok(n)
+}
+
+/// 算術式の構文解析器
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    /// 現在位置の字句が演算子opなら読み込み位置を進めて真を返す。
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 現在位置の字句がopsのいずれかの演算子ならそれを返し、読み込み位置を進める。
+    fn eat_any(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// 式が途中で終わった場合や、予期しない字句の場合のエラー。
+    fn unexpected(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some(token) => format!("構文エラーです。（{}）", token_str(token)),
+            None => "オペランドが必要です。".to_string(),
+        }
+    }
+
+    /// ,で区切られた式。
+    fn parse_comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_assign()?;
+        while self.eat(",") {
+            expr = Expr::Comma(Box::new(expr), Box::new(self.parse_assign()?));
+        }
+        Ok(expr)
+    }
+
+    /// 代入式。代入演算子は右結合。
+    fn parse_assign(&mut self) -> Result<Expr, String> {
+        const ASSIGN_OPS: [&str; 11] = [
+            "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+        ];
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+            && ASSIGN_OPS.contains(op)
+        {
+            let (name, op) = (name.clone(), *op);
+            self.pos += 2;
+            let value = Box::new(self.parse_assign()?);
+            let op = op.strip_suffix('=').filter(|o| !o.is_empty());
+            return Ok(Expr::Assign { name, op, value });
+        }
+        self.parse_cond()
+    }
+
+    /// 三項演算子。
+    fn parse_cond(&mut self) -> Result<Expr, String> {
+        let cond = self.parse_binary(0)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+        let then = self.parse_comma()?;
+        if !self.eat(":") {
+            return Err("`:'が必要です。".to_string());
+        }
+        let els = self.parse_assign()?;
+        Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(els)))
+    }
+
+    /// BINARY_LEVELSのlevel番目以降の優先順位の二項演算子の式。いずれも左結合。
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = BINARY_LEVELS.get(level) else {
+            return self.parse_pow();
+        };
+        let mut expr = self.parse_binary(level + 1)?;
+        while let Some(op) = self.eat_any(ops) {
+            let rhs = self.parse_binary(level + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    /// べき乗。右結合。
+    fn parse_pow(&mut self) -> Result<Expr, String> {
+        let base = self.parse_unary()?;
+        if self.eat("**") {
+            let exp = self.parse_pow()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exp)));
+        }
+        Ok(base)
+    }
+
+    /// 単項演算子と、前置のインクリメント、デクリメント。
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if let Some(op) = self.eat_any(&["++", "--"]) {
+            let Some(Token::Name(name)) = self.tokens.get(self.pos).cloned() else {
+                return Err(format!("{}: 変数名が必要です。", op));
+            };
+            self.pos += 1;
+            let delta = if op == "++" { 1 } else { -1 };
+            return Ok(Expr::IncDec {
+                name,
+                delta,
+                prefix: true,
+            });
+        }
+        if let Some(op) = self.eat_any(&["-", "+", "!", "~"]) {
+            return Ok(Expr::Unary(op, Box::new(self.parse_unary()?)));
+        }
+        self.parse_postfix()
+    }
+
+    /// 数値、変数、括弧で囲まれた式と、後置のインクリメント、デクリメント。
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        match token {
+            Some(Token::Num(s)) => {
+                self.pos += 1;
+                Ok(Expr::Num(parse_number(&s)?))
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                match self.eat_any(&["++", "--"]) {
+                    Some(op) => Ok(Expr::IncDec {
+                        name,
+                        delta: if op == "++" { 1 } else { -1 },
+                        prefix: false,
+                    }),
+                    None => Ok(Expr::Var(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                if !self.eat(")") {
+                    return Err("`)'が必要です。".to_string());
+                }
+                Ok(expr)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+/// 算術式の評価器
+struct Evaluator<'a> {
+    vars: &'a mut Vars,
+    depth: usize, // 変数の値を式として評価している深さ
+}
+
+impl Evaluator<'_> {
+    /// 構文木を評価。
+    fn eval(&mut self, expr: &Expr) -> Result<i64, String> {
+        let value = match expr {
+            Expr::Num(n) => *n,
+            Expr::Var(name) => self.get(name)?,
+            Expr::Unary(op, e) => {
+                let v = self.eval(e)?;
+                match *op {
+                    "-" => v.wrapping_neg(),
+                    "!" => (v == 0) as i64,
+                    "~" => !v,
+                    _ => v,
+                }
+            }
+            Expr::Binary("&&", lhs, rhs) => (self.eval(lhs)? != 0 && self.eval(rhs)? != 0) as i64,
+            Expr::Binary("||", lhs, rhs) => (self.eval(lhs)? != 0 || self.eval(rhs)? != 0) as i64,
+            Expr::Binary(op, lhs, rhs) => {
+                let l = self.eval(lhs)?;
+                let r = self.eval(rhs)?;
+                apply(op, l, r)?
+            }
+            Expr::Cond(cond, then, els) => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(els)?
+                }
+            }
+            Expr::Assign { name, op, value } => {
+                let mut v = self.eval(value)?;
+                if let Some(op) = op {
+                    v = apply(op, self.get(name)?, v)?;
+                }
+                self.set(name, v)?;
+                v
+            }
+            Expr::IncDec {
+                name,
+                delta,
+                prefix,
+            } => {
+                let old = self.get(name)?;
+                let new = old.wrapping_add(*delta);
+                self.set(name, new)?;
+                if *prefix { new } else { old }
+            }
+            Expr::Comma(lhs, rhs) => {
+                self.eval(lhs)?;
+                self.eval(rhs)?
+            }
+        };
+        Ok(value)
+    }
+
+    /// 変数の値。設定されていないか空なら0で、それ以外は値を算術式として評価する。
+    fn get(&mut self, name: &str) -> Result<i64, String> {
+        let value = match self.vars.get(name) {
+            Some(value) if !value.trim().is_empty() => value.to_string(),
+            _ => return Ok(0),
+        };
+        eval_depth(&value, self.vars, self.depth + 1)
+    }
+
+    /// 変数に値を代入。
+    fn set(&mut self, name: &str, value: i64) -> Result<(), String> {
+        self.vars
+            .set(name, value.to_string())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// 二項演算子を適用。
+fn apply(op: &str, l: i64, r: i64) -> Result<i64, String> {
+    let value = match op {
+        "+" => l.wrapping_add(r),
+        "-" => l.wrapping_sub(r),
+        "*" => l.wrapping_mul(r),
+        "/" | "%" if r == 0 => return Err("0で除算しました。".to_string()),
+        "/" => l.wrapping_div(r),
+        "%" => l.wrapping_rem(r),
+        "**" if r < 0 => return Err("指数が負の値です。".to_string()),
+        "**" => l.wrapping_pow(r.min(u32::MAX as i64) as u32),
+        "<<" => l.wrapping_shl(r as u32),
+        ">>" => l.wrapping_shr(r as u32),
+        "&" => l & r,
+        "|" => l | r,
+        "^" => l ^ r,
+        "<" => (l < r) as i64,
+        ">" => (l > r) as i64,
+        "<=" => (l <= r) as i64,
+        ">=" => (l >= r) as i64,
+        "==" => (l == r) as i64,
+        "!=" => (l != r) as i64,
+        _ => unreachable!(),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 変数が空の状態で式を評価。
+    fn eval_new(expr: &str) -> Result<i64, String> {
+        eval(expr, &mut Vars::default())
+    }
+
+    #[test]
+    fn precedence() {
+        let table = [
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("1 - 2 - 3", -4),
+            ("12 / 2 / 3", 2),
+            ("1 << 2 + 1", 8),
+            ("1 < 2 == 1", 1),
+            ("5 & 3 | 8", 9),
+            ("1 | 2 ^ 3", 1),
+            ("1 || 0 && 0", 1),
+            ("-2 ** 2", 4),
+            ("!0 + ~0", 0),
+            ("7 % -3", 1),
+            ("-7 / 2", -3),
+            ("0x1f + 017 + 2#101", 51),
+            ("", 0),
+        ];
+        for (expr, expected) in table {
+            assert_eq!(eval_new(expr), Ok(expected), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn right_assoc() {
+        assert_eq!(eval_new("2 ** 3 ** 2"), Ok(512));
+
+        let mut vars = Vars::default();
+        assert_eq!(eval("a = b = 3", &mut vars), Ok(3));
+        assert_eq!(vars.get("a"), Some("3"));
+        assert_eq!(vars.get("b"), Some("3"));
+
+        assert_eq!(eval("a += b *= 2", &mut vars), Ok(9));
+        assert_eq!(vars.get("a"), Some("9"));
+        assert_eq!(vars.get("b"), Some("6"));
+    }
+
+    #[test]
+    fn inc_dec() {
+        let mut vars = Vars::default();
+        vars.set("i", "5".to_string()).unwrap();
+        let table = [("i++", 5), ("i", 6), ("++i", 7), ("i--", 7), ("--i", 5)];
+        for (expr, expected) in table {
+            assert_eq!(eval(expr, &mut vars), Ok(expected), "{}", expr);
+        }
+        assert_eq!(eval("x = 1, x <<= 4, x |= 1", &mut vars), Ok(17));
+    }
+
+    #[test]
+    fn var_as_expr() {
+        let mut vars = Vars::default();
+        vars.set("a", "b + 1".to_string()).unwrap();
+        vars.set("b", "2".to_string()).unwrap();
+        vars.set("empty", "".to_string()).unwrap();
+        assert_eq!(eval("a * 2 + unset + empty", &mut vars), Ok(6));
+
+        // 自分自身を参照する変数は、再帰の上限でエラーになる
+        vars.set("r", "r".to_string()).unwrap();
+        assert!(eval("r", &mut vars).is_err());
+    }
+
+    #[test]
+    fn cond_short_circuit() {
+        let mut vars = Vars::default();
+        let table = [
+            ("1 ? 2 : 3", 2),
+            ("0 ? 1 : 2 ? 3 : 4", 3),
+            ("1 ? 5 : (z = 9)", 5),
+            ("0 ? (z = 9) : 6", 6),
+            ("0 && (z = 1)", 0),
+            ("1 || (z = 2)", 1),
+        ];
+        for (expr, expected) in table {
+            assert_eq!(eval(expr, &mut vars), Ok(expected), "{}", expr);
+        }
+        assert_eq!(vars.get("z"), None); // 評価されなかった側の代入は行われない
+    }
+
+    #[test]
+    fn div_by_zero() {
+        for expr in ["1 / 0", "5 % 0", "x /= 0", "y %= 0", "1 ? 2 / (1 - 1) : 0"] {
+            assert!(eval_new(expr).is_err(), "{}", expr);
+        }
+        // 短絡評価される側の0除算はエラーにならない
+        assert_eq!(eval_new("0 && 1 / 0"), Ok(0));
+    }
+
+    #[test]
+    fn overflow() {
+        // bashと同様に、桁あふれは2の補数で折り返す
+        let table = [
+            ("9223372036854775807 + 1", i64::MIN),
+            ("-9223372036854775807 - 1", i64::MIN),
+            ("-9223372036854775808", i64::MIN),
+            ("2 ** 63", i64::MIN),
+            ("2 ** 64", 0),
+            ("(-9223372036854775807 - 1) / -1", i64::MIN),
+            ("(-9223372036854775807 - 1) % -1", 0),
+            ("4611686018427387904 * 4", 0),
+        ];
+        for (expr, expected) in table {
+            assert_eq!(eval_new(expr), Ok(expected), "{}", expr);
+        }
+        assert!(eval_new("2 ** -1").is_err());
+    }
+
+    #[test]
+    fn syntax_error() {
+        for expr in [
+            "1 +", "(1", "1 ? 2", "1 2", "3 = 4", "++1", "1 $ 2", "10#1a",
+        ] {
+            assert!(eval_new(expr).is_err(), "{}", expr);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::lexer::{Word, WordPart};
+
+/// ブレース展開の対象となる単語の要素
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item<'a> {
+    Char(char),         // クォートされていない文字
+    Part(&'a WordPart), // クォートされた文字列やパラメータ展開など、展開の対象外の要素
+}
+
+/// 単語をブレース展開。{a,b}は要素ごとに、{1..10..2}や{a..z}は範囲の値ごとに別の単語とする。
+/// クォートされた{、}、,は対象外で、展開できない{...}はそのまま残す。
+pub fn expand(word: &Word) -> Vec<Word> {
+    let mut items = Vec::new();
+    for part in word.parts.iter() {
+        match part {
+            WordPart::Lit(s) => items.extend(s.chars().map(Item::Char)),
+            part => items.push(Item::Part(part)),
+        }
+    }
+    expand_items(&items).iter().map(|w| to_word(w)).collect()
+}
+
+/// 要素の列をブレース展開。左にある{から順に展開し、残りの部分は再帰的に展開する。
+fn expand_items<'a>(items: &[Item<'a>]) -> Vec<Vec<Item<'a>>> {
+    for (i, item) in items.iter().enumerate() {
+        if *item != Item::Char('{') {
+            continue;
+        }
+        let Some((end, commas)) = find_close(items, i) else {
+            continue;
+        };
+
+        let alts: Vec<Vec<Item>> = if commas.is_empty() {
+            match sequence(&items[i + 1..end]) {
+                Some(seq) => seq
+                    .into_iter()
+                    .map(|s| s.chars().map(Item::Char).collect())
+                    .collect(),
+                None => continue, // {a}のように展開できないものはそのまま
+            }
+        } else {
+            // ,で区切られたそれぞれの要素も展開する
+            let mut bounds = vec![i];
+            bounds.extend(commas);
+            bounds.push(end);
+            bounds
+                .windows(2)
+                .flat_map(|w| expand_items(&items[w[0] + 1..w[1]]))
+                .collect()
+        };
+
+        let rest = expand_items(&items[end + 1..]);
+        let mut result = Vec::new();
+        for alt in alts.iter() {
+            for r in rest.iter() {
+                let mut v = items[..i].to_vec();
+                v.extend(alt);
+                v.extend(r);
+                result.push(v);
+            }
+        }
+        return result;
+    }
+    vec![items.to_vec()]
+}
+
+/// items[start]の{に対応する}の位置と、その間の入れ子になっていない,の位置を返す。
+fn find_close(items: &[Item], start: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, item) in items.iter().enumerate().skip(start + 1) {
+        match item {
+            Item::Char('{') => depth += 1,
+            Item::Char('}') if depth == 0 => return Some((i, commas)),
+            Item::Char('}') => depth -= 1,
+            Item::Char(',') if depth == 0 => commas.push(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// {x..y}、{x..y..step}の中身を解釈し、範囲の値の列を返す。
+/// xとyは整数か1文字で、整数の先頭に0があれば桁数を揃える。
+fn sequence(items: &[Item]) -> Option<Vec<String>> {
+    let mut s = String::new();
+    for item in items {
+        match item {
+            Item::Char(c) => s.push(*c),
+            Item::Part(_) => return None,
+        }
+    }
+
+    let fields: Vec<&str> = s.split("..").collect();
+    let (start, end, step) = match fields.as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (*start, *end, step.parse::<i64>().ok()?.unsigned_abs()),
+        _ => return None,
+    };
+    let step = step.max(1) as usize;
+
+    if let (Ok(x), Ok(y)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        // {01..10}のように先頭に0があれば、長い方の桁数に揃える
+        let padded = |s: &str| {
+            let digits = s.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let values: Vec<i64> = if x <= y {
+            (x..=y).step_by(step).collect()
+        } else {
+            (y..=x).rev().step_by(step).collect()
+        };
+        return Some(
+            values
+                .into_iter()
+                .map(|n| {
+                    if n < 0 {
+                        format!("-{:0w$}", -n, w = width.saturating_sub(1)) // 符号も桁数に含める
+                    } else {
+                        format!("{:0w$}", n, w = width)
+                    }
+                })
+                .collect(),
+        );
+    }
+
+    let (mut x, mut y) = (start.chars(), end.chars());
+    let (Some(x), None, Some(y), None) = (x.next(), x.next(), y.next(), y.next()) else {
+        return None;
+    };
+    if !x.is_ascii_alphabetic() || !y.is_ascii_alphabetic() {
+        return None;
+    }
+    let (x, y) = (x as u8, y as u8);
+    let values: Vec<u8> = if x <= y {
+        (x..=y).step_by(step).collect()
+    } else {
+        (y..=x).rev().step_by(step).collect()
+    };
+    Some(
+        values
+            .into_iter()
+            .map(|c| (c as char).to_string())
+            .collect(),
+    )
+}
+
+/// 要素の列を単語に戻す。
+fn to_word(items: &[Item]) -> Word {
+    let mut parts = Vec::new();
+    for item in items {
+        match item {
+            Item::Char(c) => Word::push_lit(&mut parts, *c),
+            Item::Part(part) => parts.push((*part).clone()),
+        }
+    }
+    Word { parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// クォートされていない文字列の単語をブレース展開し、クォートを復元した文字列の列を返す。
+    fn expand_lit(s: &str) -> Vec<String> {
+        expand_parts(vec![WordPart::Lit(s.to_string())])
+    }
+
+    /// 要素を指定した単語をブレース展開し、クォートを復元した文字列の列を返す。
+    fn expand_parts(parts: Vec<WordPart>) -> Vec<String> {
+        expand(&Word { parts })
+            .iter()
+            .map(|w| w.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn list() {
+        let table = [
+            ("{a,b,c}", vec!["a", "b", "c"]),
+            ("x{a,b}y", vec!["xay", "xby"]),
+            ("{a,{b,c}}d", vec!["ad", "bd", "cd"]),
+            ("{a,b}{1,2}", vec!["a1", "a2", "b1", "b2"]),
+            ("x{,}y", vec!["xy", "xy"]),
+            ("src/{bin,lib}", vec!["src/bin", "src/lib"]),
+        ];
+        for (input, expected) in table {
+            assert_eq!(expand_lit(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn range() {
+        let table = [
+            ("{1..5}", vec!["1", "2", "3", "4", "5"]),
+            ("{5..1}", vec!["5", "4", "3", "2", "1"]),
+            ("{-1..-3}", vec!["-1", "-2", "-3"]),
+            ("{10..1..3}", vec!["10", "7", "4", "1"]),
+            ("{1..10..-4}", vec!["1", "5", "9"]),
+            ("{1..2..0}", vec!["1", "2"]),
+            ("{a..e..2}", vec!["a", "c", "e"]),
+            ("{z..w}", vec!["z", "y", "x", "w"]),
+            (
+                "file{1..3}.txt",
+                vec!["file1.txt", "file2.txt", "file3.txt"],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(expand_lit(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn padding() {
+        let expected: Vec<String> = (1..=10).map(|n| format!("{:02}", n)).collect();
+        assert_eq!(expand_lit("{01..10}"), expected);
+
+        // 負の値も、符号を含めて桁数を揃える
+        assert_eq!(
+            expand_lit("{-05..5}"),
+            [
+                "-05", "-04", "-03", "-02", "-01", "000", "001", "002", "003", "004", "005"
+            ]
+        );
+        assert_eq!(expand_lit("{1..003}"), ["001", "002", "003"]);
+    }
+
+    #[test]
+    fn not_expanded() {
+        for input in [
+            "{a}",
+            "{}",
+            "{a..5}",
+            "{1..2..x}",
+            "{a,b",
+            "a,b}",
+            "{1...3}",
+        ] {
+            assert_eq!(expand_lit(input), [input], "{}", input);
+        }
+    }
+
+    #[test]
+    fn quoted() {
+        let quoted = |s: &str| WordPart::Quoted(s.to_string());
+        let lit = |s: &str| WordPart::Lit(s.to_string());
+
+        // "{a,b}"
+        let dquoted = vec![WordPart::DQuoted(vec![lit("{a,b}")])];
+        assert_eq!(expand_parts(dquoted), ["\"{a,b}\""]);
+
+        // \{a,b}
+        assert_eq!(expand_parts(vec![quoted("{"), lit("a,b}")]), ["'{'a,b}"]);
+
+        // {a','b}
+        let parts = vec![lit("{a"), quoted(","), lit("b}")];
+        assert_eq!(expand_parts(parts), ["{a','b}"]);
+
+        // {'a',b}はクォートされた要素もそのまま残して展開する
+        let parts = vec![lit("{"), quoted("a"), lit(",b}")];
+        assert_eq!(expand_parts(parts), ["'a'", "b"]);
+    }
+}
@@ -0,0 +1,200 @@
+use nix::unistd::{AccessFlags, access, isatty};
+use std::{
+    fs,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+};
+
+/// testや[[ ]]の単項演算子なら真。
+pub fn is_unary_op(op: &str) -> bool {
+    matches!(
+        op,
+        "-e" | "-f"
+            | "-d"
+            | "-r"
+            | "-w"
+            | "-x"
+            | "-s"
+            | "-L"
+            | "-h"
+            | "-p"
+            | "-S"
+            | "-b"
+            | "-c"
+            | "-t"
+            | "-z"
+            | "-n"
+    )
+}
+
+/// testや[[ ]]の二項演算子なら真。
+pub fn is_binary_op(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "=="
+            | "!="
+            | "<"
+            | ">"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
+    )
+}
+
+/// 単項演算子を評価。
+pub fn unary(op: &str, arg: &str) -> bool {
+    let meta = || fs::metadata(arg);
+    match op {
+        "-z" => arg.is_empty(),
+        "-n" => !arg.is_empty(),
+        "-e" => meta().is_ok(),
+        "-f" => meta().is_ok_and(|m| m.is_file()),
+        "-d" => meta().is_ok_and(|m| m.is_dir()),
+        "-s" => meta().is_ok_and(|m| m.len() > 0),
+        "-p" => meta().is_ok_and(|m| m.file_type().is_fifo()),
+        "-S" => meta().is_ok_and(|m| m.file_type().is_socket()),
+        "-b" => meta().is_ok_and(|m| m.file_type().is_block_device()),
+        "-c" => meta().is_ok_and(|m| m.file_type().is_char_device()),
+        "-L" | "-h" => fs::symlink_metadata(arg).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => access(arg, AccessFlags::R_OK).is_ok(),
+        "-w" => access(arg, AccessFlags::W_OK).is_ok(),
+        "-x" => access(arg, AccessFlags::X_OK).is_ok(),
+        "-t" => arg.parse().is_ok_and(|fd| isatty(fd).unwrap_or(false)),
+        _ => false,
+    }
+}
+
+/// 二項演算子を評価。整数の比較で引数が整数でない場合はエラー。
+pub fn binary(lhs: &str, op: &str, rhs: &str) -> Result<bool, String> {
+    let int = |s: &str| {
+        s.trim()
+            .parse::<i64>()
+            .map_err(|_| format!("{}: 整数の式が必要です。", s))
+    };
+    let mtime = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    let result = match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" => int(lhs)? == int(rhs)?,
+        "-ne" => int(lhs)? != int(rhs)?,
+        "-lt" => int(lhs)? < int(rhs)?,
+        "-le" => int(lhs)? <= int(rhs)?,
+        "-gt" => int(lhs)? > int(rhs)?,
+        "-ge" => int(lhs)? >= int(rhs)?,
+        "-nt" => match (mtime(lhs), mtime(rhs)) {
+            (Some(l), Some(r)) => l > r,
+            (l, r) => l.is_some() && r.is_none(),
+        },
+        "-ot" => match (mtime(lhs), mtime(rhs)) {
+            (Some(l), Some(r)) => l < r,
+            (l, r) => l.is_none() && r.is_some(),
+        },
+        "-ef" => match (fs::metadata(lhs), fs::metadata(rhs)) {
+            (Ok(l), Ok(r)) => l.dev() == r.dev() && l.ino() == r.ino(),
+            _ => false,
+        },
+        _ => return Err(format!("{}: 二項演算子が必要です。", op)),
+    };
+    Ok(result)
+}
+
+/// testコマンドの引数を評価。
+///
+/// POSIXの規定どおり、引数が4つ以下の場合は引数の数によって解釈を決め、
+/// それより多い場合は!、-a、-o、括弧を含む式として解釈する。
+pub fn test(args: &[&str]) -> Result<bool, String> {
+    match args {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        ["!", arg] => Ok(arg.is_empty()),
+        [op, arg] if is_unary_op(op) => Ok(unary(op, arg)),
+        [op, _] => Err(format!("{}: 単項演算子が必要です。", op)),
+        [lhs, op, rhs] if is_binary_op(op) => binary(lhs, op, rhs),
+        ["!", rest @ ..] if args.len() <= 4 => Ok(!test(rest)?),
+        ["(", inner @ .., ")"] if args.len() <= 4 => test(inner),
+        _ => {
+            let mut parser = TestParser { args, pos: 0 };
+            let result = parser.parse_or()?;
+            match parser.args.get(parser.pos) {
+                Some(arg) => Err(format!("{}: 余分な引数です。", arg)),
+                None => Ok(result),
+            }
+        }
+    }
+}
+
+/// 引数が5つ以上のtestコマンドの式の解析器
+struct TestParser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl TestParser<'_> {
+    /// 現在位置の引数を返し、読み込み位置を1つ進める。
+    fn next(&mut self) -> Result<&str, String> {
+        let arg = self.args.get(self.pos).ok_or("引数が足りません。")?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    /// -oで連結された式。
+    fn parse_or(&mut self) -> Result<bool, String> {
+        let mut result = self.parse_and()?;
+        while self.args.get(self.pos) == Some(&"-o") {
+            self.pos += 1;
+            result |= self.parse_and()?;
+        }
+        Ok(result)
+    }
+
+    /// -aで連結された式。
+    fn parse_and(&mut self) -> Result<bool, String> {
+        let mut result = self.parse_not()?;
+        while self.args.get(self.pos) == Some(&"-a") {
+            self.pos += 1;
+            result &= self.parse_not()?;
+        }
+        Ok(result)
+    }
+
+    /// 先頭に!が付いた式。
+    fn parse_not(&mut self) -> Result<bool, String> {
+        if self.args.get(self.pos) == Some(&"!") {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    /// 括弧で囲まれた式、単項演算子、二項演算子、または1つの文字列。
+    fn parse_primary(&mut self) -> Result<bool, String> {
+        let rest = &self.args[self.pos..];
+        match rest {
+            ["(", ..] => {
+                self.pos += 1;
+                let result = self.parse_or()?;
+                match self.next()? {
+                    ")" => Ok(result),
+                    arg => Err(format!("{}: `)'が必要です。", arg)),
+                }
+            }
+            [lhs, op, rhs, ..] if is_binary_op(op) => {
+                self.pos += 3;
+                binary(lhs, op, rhs)
+            }
+            [op, arg, ..] if is_unary_op(op) => {
+                self.pos += 2;
+                Ok(unary(op, arg))
+            }
+            _ => Ok(!self.next()?.is_empty()),
+        }
+    }
+}
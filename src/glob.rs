@@ -0,0 +1,286 @@
+use crate::pattern::{Pattern, unescape};
+use std::fs;
+
+/// パターンとパス名展開の動作を変えるオプション。shoptで設定する。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GlobOptions {
+    pub nullglob: bool,   // マッチしなければ単語を削除
+    pub failglob: bool,   // マッチしなければエラー
+    pub dotglob: bool,    // .で始まるファイル名にもマッチ
+    pub nocaseglob: bool, // 大文字と小文字を区別しない
+    pub globstar: bool,   // **で0個以上のディレクトリにマッチ
+    pub extglob: bool,    // ?(...)などの拡張パターンを使用
+}
+
+/// フィールドをパス名展開。patはクォートされた部分をエスケープしたもの。
+/// マッチするファイルがなければ、nullglob、failglobに従う。
+pub fn expand(pat: &str, opts: &GlobOptions) -> Result<Vec<String>, String> {
+    if Pattern::new(pat, opts.extglob).is_literal() {
+        return Ok(vec![unescape(pat)]);
+    }
+
+    let paths = glob(pat, opts);
+    if !paths.is_empty() {
+        Ok(paths)
+    } else if opts.failglob {
+        Err(format!("{}: 一致するファイルがありません。", unescape(pat)))
+    } else if opts.nullglob {
+        Ok(Vec::new())
+    } else {
+        Ok(vec![unescape(pat)])
+    }
+}
+
+/// パターンにマッチするパスを、名前順に並べて返す。
+///
+/// patはpattern::escapeでクォートされた部分をエスケープしたもので、/で区切った要素ごとにマッチさせる。
+pub fn glob(pat: &str, opts: &GlobOptions) -> Vec<String> {
+    let (mut paths, rest) = match pat.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pat),
+    };
+
+    let comps: Vec<&str> = rest.split('/').collect();
+    for (i, comp) in comps.iter().enumerate() {
+        let last = i == comps.len() - 1;
+        if comp.is_empty() {
+            if last {
+                // 末尾の/はディレクトリにのみマッチ
+                paths.retain(|p| fs::metadata(p).is_ok_and(|m| m.is_dir()));
+                for p in paths.iter_mut().filter(|p| !p.ends_with('/')) {
+                    p.push('/');
+                }
+            }
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for base in paths.iter() {
+            if *comp == "**" && opts.globstar {
+                // 末尾の**は全てのファイルとディレクトリに、それ以外は0個以上のディレクトリにマッチ
+                if !last {
+                    next.push(base.clone());
+                }
+                walk(base, !last, opts.dotglob, &mut next);
+                continue;
+            }
+
+            let pattern = Pattern::new(comp, opts.extglob).nocase(opts.nocaseglob);
+            if pattern.is_literal() {
+                next.push(join(base, &unescape(comp)));
+                continue;
+            }
+
+            // .で始まるファイル名は、パターンも.で始まる場合にのみマッチ
+            let dot = opts.dotglob || comp.starts_with('.') || comp.starts_with("\\.");
+            for name in read_names(base) {
+                if (dot || !name.starts_with('.')) && pattern.matches(&name) {
+                    let path = join(base, &name);
+                    if last || fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
+                        next.push(path);
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+
+    paths.retain(|p| !p.is_empty() && fs::symlink_metadata(p).is_ok());
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// dir以下のファイルとディレクトリを再帰的にoutに追加。dirs_onlyが真ならディレクトリのみ。
+/// シンボリックリンクのディレクトリはたどらない。
+fn walk(dir: &str, dirs_only: bool, dotglob: bool, out: &mut Vec<String>) {
+    for name in read_names(dir) {
+        if !dotglob && name.starts_with('.') {
+            continue;
+        }
+        let path = join(dir, &name);
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+        if is_dir {
+            out.push(path.clone());
+            walk(&path, dirs_only, dotglob, out);
+        } else if !dirs_only {
+            out.push(path);
+        }
+    }
+}
+
+/// ディレクトリ中のファイル名の一覧。dirが空ならカレントディレクトリ。
+/// 読み込めない場合は空とする。
+fn read_names(dir: &str) -> Vec<String> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect()
+}
+
+/// ディレクトリのパスにファイル名を連結。
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::escape;
+    use std::path::PathBuf;
+
+    /// テスト用の一時ディレクトリ。破棄する時に中身ごと削除する。
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        /// 一時ディレクトリを作成し、pathsのファイルを作成する。/で終わるものはディレクトリ。
+        fn new(name: &str, paths: &[&str]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("zerosh-glob-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            for p in paths {
+                let path = dir.join(p);
+                if p.ends_with('/') {
+                    fs::create_dir_all(path).unwrap();
+                } else {
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    fs::write(path, "").unwrap();
+                }
+            }
+            TempDir(dir)
+        }
+
+        /// 一時ディレクトリの中でパターンを展開し、一時ディレクトリからの相対パスを返す。
+        fn glob(&self, pat: &str, opts: &GlobOptions) -> Vec<String> {
+            let prefix = format!("{}/", self.0.to_str().unwrap());
+            glob(&format!("{}{}", escape(&prefix), pat), opts)
+                .into_iter()
+                .map(|p| p.strip_prefix(&prefix).unwrap().to_string())
+                .collect()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn sorted() {
+        let dir = TempDir::new("sorted", &["b.txt", "a.txt", "C.txt", "a.rs", "ab/"]);
+        let opts = GlobOptions::default();
+        assert_eq!(dir.glob("*.txt", &opts), ["C.txt", "a.txt", "b.txt"]);
+        assert_eq!(dir.glob("a*", &opts), ["a.rs", "a.txt", "ab"]);
+        assert_eq!(dir.glob("?.*", &opts), ["C.txt", "a.rs", "a.txt", "b.txt"]);
+        assert!(dir.glob("*.md", &opts).is_empty());
+
+        let opts = GlobOptions {
+            nocaseglob: true,
+            ..Default::default()
+        };
+        assert_eq!(dir.glob("c.*", &opts), ["C.txt"]);
+    }
+
+    #[test]
+    fn dotglob() {
+        let dir = TempDir::new("dotglob", &["a", ".b", ".c/d", "e/.f"]);
+        let opts = GlobOptions::default();
+        assert_eq!(dir.glob("*", &opts), ["a", "e"]);
+        assert_eq!(dir.glob(".*", &opts), [".b", ".c"]);
+        assert_eq!(dir.glob("*/*", &opts), Vec::<String>::new());
+        assert_eq!(dir.glob(".c/*", &opts), [".c/d"]);
+
+        let opts = GlobOptions {
+            dotglob: true,
+            ..Default::default()
+        };
+        assert_eq!(dir.glob("*", &opts), [".b", ".c", "a", "e"]);
+        assert_eq!(dir.glob("*/*", &opts), [".c/d", "e/.f"]);
+    }
+
+    #[test]
+    fn trailing_slash() {
+        let dir = TempDir::new("slash", &["a/", "b/c/", "d", "e.txt"]);
+        let opts = GlobOptions::default();
+        assert_eq!(dir.glob("*/", &opts), ["a/", "b/"]);
+        assert_eq!(dir.glob("*/*/", &opts), ["b/c/"]);
+        assert!(dir.glob("*.txt/", &opts).is_empty());
+    }
+
+    #[test]
+    fn globstar() {
+        let dir = TempDir::new(
+            "globstar",
+            &[
+                "a.txt",
+                "x/b.txt",
+                "x/y/c.txt",
+                "x/y/d.rs",
+                "x/.h/e.txt",
+                "z/",
+            ],
+        );
+        let opts = GlobOptions {
+            globstar: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            dir.glob("**/*.txt", &opts),
+            ["a.txt", "x/b.txt", "x/y/c.txt"]
+        );
+        assert_eq!(
+            dir.glob("**", &opts),
+            ["a.txt", "x", "x/b.txt", "x/y", "x/y/c.txt", "x/y/d.rs", "z"]
+        );
+        assert_eq!(dir.glob("x/**/", &opts), ["x/", "x/y/"]); // 0個のディレクトリにもマッチ
+        assert_eq!(dir.glob("x/**/*.rs", &opts), ["x/y/d.rs"]);
+
+        // globstarが無効なら、**は*と同じ
+        let opts = GlobOptions::default();
+        assert_eq!(dir.glob("**/*.txt", &opts), ["x/b.txt"]);
+    }
+
+    #[test]
+    fn no_match() {
+        let dir = TempDir::new("nomatch", &["a"]);
+        let pat = format!("{}/*.txt", escape(dir.0.to_str().unwrap()));
+        let unmatched = format!("{}/*.txt", dir.0.to_str().unwrap());
+
+        // マッチしなければパターンをそのまま残す
+        let opts = GlobOptions::default();
+        assert_eq!(expand(&pat, &opts), Ok(vec![unmatched.clone()]));
+
+        let opts = GlobOptions {
+            nullglob: true,
+            ..Default::default()
+        };
+        assert_eq!(expand(&pat, &opts), Ok(vec![]));
+
+        // nullglobよりfailglobを優先する
+        let opts = GlobOptions {
+            nullglob: true,
+            failglob: true,
+            ..Default::default()
+        };
+        assert!(expand(&pat, &opts).unwrap_err().starts_with(&unmatched));
+
+        // 特殊文字を含まないパターンは、ファイルがなくてもそのまま返す
+        let opts = GlobOptions {
+            failglob: true,
+            ..Default::default()
+        };
+        assert_eq!(expand(r"\*x", &opts), Ok(vec!["*x".to_string()]));
+    }
+}
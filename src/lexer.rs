@@ -0,0 +1,1451 @@
+use crate::parser::{ParseError, ParseErrorKind, is_name};
+use std::fmt;
+
+/// 入力中の位置。行、列ともに1始まり。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// パラメータ展開。$NAME、${NAME}、${NAME[index]}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,          // 変数名
+    pub index: Option<String>, // 配列の添字。@なら全ての要素
+    pub op: Option<ParamOp>,   // ${NAME:-word}などの演算
+    pub braced: bool,          // ${...}の形式なら真
+}
+
+/// パラメータ展開の演算
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamOp {
+    Length, // ${#NAME}
+    Test {
+        op: char,    // -、=、?、+のいずれか
+        colon: bool, // ${NAME:-word}のように:があれば、空文字列も設定されていないとみなす
+        word: Word,
+    },
+    Trim {
+        suffix: bool,  // %、%%なら真で、末尾から取り除く
+        longest: bool, // ##、%%なら真で、最長一致の部分を取り除く
+        pattern: Word,
+    },
+    Replace {
+        all: bool, // //なら真で、全ての一致を置換
+        pattern: Word,
+        rep: Word,
+    },
+    Substr {
+        offset: Word,
+        length: Option<Word>,
+    },
+    Case {
+        upper: bool, // ^、^^なら真で、大文字に変換
+        all: bool,   // ^^、,,なら真で、全ての文字を変換
+    },
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.braced {
+            return write!(f, "${}", self.name);
+        }
+        write!(f, "${{")?;
+        if self.op == Some(ParamOp::Length) {
+            write!(f, "#")?;
+        }
+        write!(f, "{}", self.name)?;
+        if let Some(index) = &self.index {
+            write!(f, "[{}]", index)?;
+        }
+
+        match &self.op {
+            None | Some(ParamOp::Length) => (),
+            Some(ParamOp::Test { op, colon, word }) => {
+                write!(f, "{}{}{}", if *colon { ":" } else { "" }, op, word)?
+            }
+            Some(ParamOp::Trim {
+                suffix,
+                longest,
+                pattern,
+            }) => {
+                let c = if *suffix { "%" } else { "#" };
+                let op = if *longest { c.repeat(2) } else { c.to_string() };
+                write!(f, "{}{}", op, pattern)?
+            }
+            Some(ParamOp::Replace { all, pattern, rep }) => {
+                write!(f, "{}{}/{}", if *all { "//" } else { "/" }, pattern, rep)?
+            }
+            Some(ParamOp::Substr { offset, length }) => {
+                write!(f, ":{}", offset)?;
+                if let Some(length) = length {
+                    write!(f, ":{}", length)?;
+                }
+            }
+            Some(ParamOp::Case { upper, all }) => {
+                let c = if *upper { "^" } else { "," };
+                write!(f, "{}", if *all { c.repeat(2) } else { c.to_string() })?
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+/// 単語を構成する要素
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    Lit(String),            // クォートされていない文字列
+    Quoted(String),         // シングルクォート、バックスラッシュでクォートされた文字列
+    DQuoted(Vec<WordPart>), // ダブルクォートで囲まれた要素
+    Param(Param),           // パラメータ展開
+    CmdSubst(String),       // $(...)、`...`によるコマンド置換。中身のコマンド文字列
+    Arith(Word),            // $((...))による算術式展開
+}
+
+/// 単語。クォートの情報を保持したまま所有する。
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// クォートを除去した文字列を返す。パラメータ展開は展開せずに元の表記のまま。
+    pub fn unquote(&self) -> String {
+        fn push_parts(parts: &[WordPart], s: &mut String) {
+            for part in parts {
+                match part {
+                    WordPart::Lit(t) | WordPart::Quoted(t) => s.push_str(t),
+                    WordPart::DQuoted(inner) => push_parts(inner, s),
+                    WordPart::Param(p) => s.push_str(&p.to_string()),
+                    WordPart::CmdSubst(src) => s.push_str(&format!("$({})", src)),
+                    WordPart::Arith(expr) => s.push_str(&format!("$(({}))", expr.unquote())),
+                }
+            }
+        }
+
+        let mut s = String::new();
+        push_parts(&self.parts, &mut s);
+        s
+    }
+
+    /// クォートを一切含まない単語ならその文字列を返す。予約語の判定に利用。
+    pub fn as_lit(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Lit(s)] => Some(s),
+            _ => None,
+        }
+    }
+
+    /// 末尾の要素がクォートされていない文字列ならそれに追加し、そうでなければ新たな要素を追加。
+    pub fn push_lit(parts: &mut Vec<WordPart>, c: char) {
+        if let Some(WordPart::Lit(s)) = parts.last_mut() {
+            s.push(c);
+        } else {
+            parts.push(WordPart::Lit(c.to_string()));
+        }
+    }
+}
+
+impl fmt::Display for Word {
+    /// クォートを復元して表示。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_parts(parts: &[WordPart], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for part in parts {
+                match part {
+                    WordPart::Lit(s) => write!(f, "{}", s)?,
+                    WordPart::Quoted(s) => write!(f, "'{}'", s.replace('\'', "'\\''"))?,
+                    WordPart::Param(p) => write!(f, "{}", p)?,
+                    WordPart::CmdSubst(src) => write!(f, "$({})", src)?,
+                    WordPart::Arith(expr) => write!(f, "$(({}))", expr)?,
+                    WordPart::DQuoted(inner) => {
+                        write!(f, "\"")?;
+                        for p in inner {
+                            match p {
+                                WordPart::Lit(s) => {
+                                    for c in s.chars() {
+                                        if matches!(c, '$' | '`' | '"' | '\\') {
+                                            write!(f, "\\")?;
+                                        }
+                                        write!(f, "{}", c)?;
+                                    }
+                                }
+                                _ => write_parts(std::slice::from_ref(p), f)?,
+                            }
+                        }
+                        write!(f, "\"")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        write_parts(&self.parts, f)
+    }
+}
+
+/// リダイレクトの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirOp {
+    In,           // <
+    Out,          // >
+    Append,       // >>
+    DupIn,        // <&
+    DupOut,       // >&
+    OutErr,       // &>
+    AppendErr,    // &>>
+    HereDoc,      // <<
+    HereDocStrip, // <<-
+    HereStr,      // <<<
+}
+
+impl fmt::Display for RedirOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RedirOp::In => "<",
+            RedirOp::Out => ">",
+            RedirOp::Append => ">>",
+            RedirOp::DupIn => "<&",
+            RedirOp::DupOut => ">&",
+            RedirOp::OutErr => "&>",
+            RedirOp::AppendErr => "&>>",
+            RedirOp::HereDoc => "<<",
+            RedirOp::HereDocStrip => "<<-",
+            RedirOp::HereStr => "<<<",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// トークンの種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Word(Word), // 単語
+    Redir {
+        fd: Option<i32>, // 2>のように明示されたファイルディスクリプタ
+        op: RedirOp,
+    },
+    HereDoc {
+        fd: Option<i32>,
+        strip: bool, // <<-なら真。行頭のタブを除去する
+        delim: Word, // 区切り文字列
+        body: Word,  // 本文。区切り文字列がクォートされていれば展開しない
+    },
+    Arith(Word), // ((...))による算術式の評価
+    Pipe,        // |
+    AndIf,       // &&
+    OrIf,        // ||
+    Semi,        // ;
+    DSemi,       // ;;
+    Amp,         // &
+    LParen,      // (
+    RParen,      // )
+    Newline,     // 改行
+    Eof,         // 入力の終わり
+}
+
+impl fmt::Display for TokenKind {
+    /// エラーメッセージ用の表示。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Word(w) => write!(f, "{}", w),
+            TokenKind::Redir { fd: Some(fd), op } => write!(f, "{}{}", fd, op),
+            TokenKind::Redir { fd: None, op } => write!(f, "{}", op),
+            TokenKind::HereDoc { strip: false, .. } => write!(f, "<<"),
+            TokenKind::HereDoc { strip: true, .. } => write!(f, "<<-"),
+            TokenKind::Arith(_) => write!(f, "(("),
+            TokenKind::Pipe => write!(f, "|"),
+            TokenKind::AndIf => write!(f, "&&"),
+            TokenKind::OrIf => write!(f, "||"),
+            TokenKind::Semi => write!(f, ";"),
+            TokenKind::DSemi => write!(f, ";;"),
+            TokenKind::Amp => write!(f, "&"),
+            TokenKind::LParen => write!(f, "("),
+            TokenKind::RParen => write!(f, ")"),
+            TokenKind::Newline => write!(f, "newline"),
+            TokenKind::Eof => write!(f, "EOF"),
+        }
+    }
+}
+
+/// 位置情報付きのトークン
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub pos: Pos, // トークンの開始位置
+}
+
+/// 本文の読み込みを待っているヒアドキュメント
+struct PendingHereDoc {
+    token: usize,  // トークン列中の位置
+    delim: String, // クォートを除去した区切り文字列
+    strip: bool,   // 行頭のタブを除去するなら真
+    quoted: bool,  // 区切り文字列がクォートされていれば真
+}
+
+/// 字句解析器
+struct Lexer {
+    chars: Vec<char>,              // 入力文字列
+    idx: usize,                    // 現在の読み込み位置
+    pos: Pos,                      // 現在の読み込み位置の行と列
+    heredocs: Vec<PendingHereDoc>, // 次の改行の後に本文を読み込むヒアドキュメント
+    extglob: bool,                 // ?(...)などの拡張パターンを単語として読み込むなら真
+}
+
+impl Lexer {
+    fn new(input: &str, extglob: bool) -> Self {
+        Lexer {
+            chars: input.chars().collect(),
+            idx: 0,
+            pos: Pos { line: 1, col: 1 },
+            heredocs: Vec::new(),
+            extglob,
+        }
+    }
+
+    /// 現在位置からn文字先の文字を返す。
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.chars.get(self.idx + n).copied()
+    }
+
+    /// 現在位置の文字を返す。
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    /// 現在位置の文字を返し、読み込み位置を1つ進める。
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.idx += 1;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
+    }
+
+    /// 入力の途中で終わった場合のエラー。
+    fn eof_error(&self, expected: char) -> ParseError {
+        ParseError {
+            pos: self.pos,
+            kind: ParseErrorKind::UnexpectedEof(Some(expected)),
+        }
+    }
+
+    /// 入力全体をトークン列に変換。末尾には必ずEofが置かれる。
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        self.read_tokens(false)
+    }
+
+    /// トークン列を読み込む。nestedが真ならコマンド置換の中身として、
+    /// 対応する)を読み込んだ時点で終了する。その)はトークン列に含めない。
+    fn read_tokens(&mut self, nested: bool) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        let mut depth = 0; // 入れ子になった括弧の深さ
+        loop {
+            let pos = self.pos;
+            let kind = match self.peek() {
+                None if nested => return Err(self.eof_error(')')),
+                None => {
+                    if let Some(h) = self.heredocs.first() {
+                        return Err(ParseError {
+                            pos,
+                            kind: ParseErrorKind::HereDocEof(h.delim.clone()),
+                        });
+                    }
+                    tokens.push(Token {
+                        kind: TokenKind::Eof,
+                        pos,
+                    });
+                    return Ok(tokens);
+                }
+                Some(' ' | '\t') => {
+                    self.next();
+                    continue;
+                }
+                Some('\\') if self.peek_at(1) == Some('\n') => {
+                    // 行の継続
+                    self.next();
+                    self.next();
+                    continue;
+                }
+                Some('#') => {
+                    // コメントは行末まで読み飛ばす
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.next();
+                    }
+                    continue;
+                }
+                Some('(') if self.peek_at(1) == Some('(') => match self.read_arith()? {
+                    Some(expr) => TokenKind::Arith(expr),
+                    None => self.read_operator(None), // (( ... ) ... )のような入れ子のサブシェル
+                },
+                Some(c) if is_operator_start(c) => self.read_operator(None),
+                Some(_) => {
+                    let word = self.read_word()?;
+
+                    // 2>のように数字のみの単語の直後にリダイレクト演算子が続く場合
+                    if let Some(s) = word.as_lit()
+                        && let Ok(fd) = s.parse::<i32>()
+                        && matches!(self.peek(), Some('<' | '>'))
+                    {
+                        self.read_operator(Some(fd))
+                    } else {
+                        TokenKind::Word(word)
+                    }
+                }
+            };
+
+            match kind {
+                TokenKind::Redir {
+                    fd,
+                    op: op @ (RedirOp::HereDoc | RedirOp::HereDocStrip),
+                } => {
+                    let kind =
+                        self.read_heredoc_delim(fd, op == RedirOp::HereDocStrip, tokens.len())?;
+                    tokens.push(Token { kind, pos });
+                }
+                TokenKind::Newline => {
+                    tokens.push(Token { kind, pos });
+                    self.read_heredoc_bodies(&mut tokens)?;
+                }
+                TokenKind::RParen if nested && depth == 0 => return Ok(tokens),
+                TokenKind::LParen | TokenKind::RParen => {
+                    depth += if kind == TokenKind::LParen { 1 } else { -1 };
+                    tokens.push(Token { kind, pos });
+                }
+                _ => tokens.push(Token { kind, pos }),
+            }
+        }
+    }
+
+    /// <<または<<-の後の区切り文字列を読み込み、ヒアドキュメントのトークンを返す。
+    /// 本文は次の改行の後で読み込む。tokenはこのトークンを置くトークン列中の位置。
+    fn read_heredoc_delim(
+        &mut self,
+        fd: Option<i32>,
+        strip: bool,
+        token: usize,
+    ) -> Result<TokenKind, ParseError> {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+
+        let pos = self.pos;
+        match self.peek() {
+            None => {
+                return Err(ParseError {
+                    pos,
+                    kind: ParseErrorKind::UnexpectedEof(None),
+                });
+            }
+            Some(c) if is_operator_start(c) => {
+                let t = self.read_operator(None);
+                return Err(ParseError {
+                    pos,
+                    kind: ParseErrorKind::UnexpectedToken(t.to_string()),
+                });
+            }
+            _ => (),
+        }
+
+        let delim = self.read_word()?;
+        let quoted = delim.parts.iter().any(|p| !matches!(p, WordPart::Lit(_)));
+        self.heredocs.push(PendingHereDoc {
+            token,
+            delim: delim.unquote(),
+            strip,
+            quoted,
+        });
+
+        Ok(TokenKind::HereDoc {
+            fd,
+            strip,
+            delim,
+            body: Word::default(),
+        })
+    }
+
+    /// 改行の直後で、待っているヒアドキュメントの本文を順に読み込む。
+    fn read_heredoc_bodies(&mut self, tokens: &mut [Token]) -> Result<(), ParseError> {
+        for h in std::mem::take(&mut self.heredocs) {
+            let mut text = String::new();
+            loop {
+                if self.peek().is_none() {
+                    return Err(ParseError {
+                        pos: self.pos,
+                        kind: ParseErrorKind::HereDocEof(h.delim),
+                    });
+                }
+
+                // 1行読み込む
+                let mut line = String::new();
+                while let Some(c) = self.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                    line.push(c);
+                }
+
+                let line = if h.strip {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == h.delim {
+                    break;
+                }
+                text.push_str(line);
+                text.push('\n');
+            }
+
+            let parts = if h.quoted {
+                vec![WordPart::Quoted(text)]
+            } else {
+                vec![WordPart::DQuoted(
+                    Lexer::new(&text, false).read_heredoc_text()?,
+                )]
+            };
+            if let TokenKind::HereDoc { body, .. } = &mut tokens[h.token].kind {
+                *body = Word { parts };
+            }
+        }
+        Ok(())
+    }
+
+    /// クォートされていない区切り文字列のヒアドキュメントの本文を、入力の終わりまで読み込む。
+    ///
+    /// バックスラッシュは `$`、`` ` ``、`\`、改行の前でのみエスケープとして扱う。
+    fn read_heredoc_text(&mut self) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = Vec::new();
+        while let Some(c) = self.next() {
+            match c {
+                '$' => self.read_param(&mut parts)?,
+                '`' => parts.push(WordPart::CmdSubst(self.read_backquoted()?)),
+                '\\' => match self.peek() {
+                    Some('\n') => {
+                        self.next(); // 行の継続
+                    }
+                    Some(e @ ('$' | '`' | '\\')) => {
+                        self.next();
+                        Word::push_lit(&mut parts, e);
+                    }
+                    _ => Word::push_lit(&mut parts, '\\'),
+                },
+                c => Word::push_lit(&mut parts, c),
+            }
+        }
+        Ok(parts)
+    }
+
+    /// 演算子を読み込む。fdはリダイレクト演算子の直前に書かれた数字。
+    fn read_operator(&mut self, fd: Option<i32>) -> TokenKind {
+        let c = self.next().unwrap();
+        let next = self.peek();
+
+        let redir = |op: RedirOp| TokenKind::Redir { fd, op };
+
+        match (c, next) {
+            ('|', Some('|')) => self.two(TokenKind::OrIf),
+            ('|', _) => TokenKind::Pipe,
+            ('&', Some('&')) => self.two(TokenKind::AndIf),
+            ('&', Some('>')) => {
+                self.two(TokenKind::Amp);
+                if self.peek() == Some('>') {
+                    self.next();
+                    redir(RedirOp::AppendErr)
+                } else {
+                    redir(RedirOp::OutErr)
+                }
+            }
+            ('&', _) => TokenKind::Amp,
+            (';', Some(';')) => self.two(TokenKind::DSemi),
+            (';', _) => TokenKind::Semi,
+            ('(', _) => TokenKind::LParen,
+            (')', _) => TokenKind::RParen,
+            ('\n', _) => TokenKind::Newline,
+            ('<', Some('&')) => self.two(redir(RedirOp::DupIn)),
+            ('<', Some('<')) => {
+                self.next();
+                match self.peek() {
+                    Some('<') => self.two(redir(RedirOp::HereStr)),
+                    Some('-') => self.two(redir(RedirOp::HereDocStrip)),
+                    _ => redir(RedirOp::HereDoc),
+                }
+            }
+            ('<', _) => redir(RedirOp::In),
+            ('>', Some('>')) => self.two(redir(RedirOp::Append)),
+            ('>', Some('&')) => self.two(redir(RedirOp::DupOut)),
+            ('>', _) => redir(RedirOp::Out),
+            _ => unreachable!(),
+        }
+    }
+
+    /// 2文字目まで演算子に含まれる場合に、読み込み位置を進めてkindを返す。
+    fn two(&mut self, kind: TokenKind) -> TokenKind {
+        self.next();
+        kind
+    }
+
+    /// 単語を1つ読み込む。
+    fn read_word(&mut self) -> Result<Word, ParseError> {
+        let mut parts = Vec::new();
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => break,
+                '(' if self.extglob
+                    && matches!(parts.last(), Some(WordPart::Lit(s)) if s.ends_with(['?', '*', '+', '@', '!'])) =>
+                {
+                    self.next();
+                    self.read_extglob(&mut parts)?;
+                }
+                c if is_operator_start(c) => break,
+                '\'' => {
+                    self.next();
+                    parts.push(WordPart::Quoted(self.read_single_quoted()?));
+                }
+                '"' => {
+                    self.next();
+                    parts.push(WordPart::DQuoted(self.read_double_quoted()?));
+                }
+                '$' => {
+                    self.next();
+                    self.read_param(&mut parts)?;
+                }
+                '`' => {
+                    self.next();
+                    parts.push(WordPart::CmdSubst(self.read_backquoted()?));
+                }
+                '\\' => {
+                    self.next();
+                    match self.next() {
+                        Some('\n') => (), // 行の継続
+                        Some(e) => parts.push(WordPart::Quoted(e.to_string())),
+                        None => {
+                            // 末尾のバックスラッシュは次の行に継続する
+                            return Err(ParseError {
+                                pos: self.pos,
+                                kind: ParseErrorKind::UnexpectedEof(None),
+                            });
+                        }
+                    }
+                }
+                _ => {
+                    self.next();
+                    Word::push_lit(&mut parts, c);
+                }
+            }
+        }
+        Ok(Word { parts })
+    }
+
+    /// !(*.txt)のような拡張パターンの括弧の中身を、対応する)まで読み込み、partsに追加する。
+    /// (は読み込み済みであること。括弧の中では|や空白も単語の一部とする。
+    fn read_extglob(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ParseError> {
+        Word::push_lit(parts, '(');
+        let mut depth = 0; // 入れ子になった括弧の深さ
+        loop {
+            match self.next() {
+                None => return Err(self.eof_error(')')),
+                Some(')') if depth == 0 => {
+                    Word::push_lit(parts, ')');
+                    return Ok(());
+                }
+                Some(c @ ('(' | ')')) => {
+                    depth += if c == '(' { 1 } else { -1 };
+                    Word::push_lit(parts, c);
+                }
+                Some('\'') => parts.push(WordPart::Quoted(self.read_single_quoted()?)),
+                Some('"') => parts.push(WordPart::DQuoted(self.read_double_quoted()?)),
+                Some('$') => self.read_param(parts)?,
+                Some('`') => parts.push(WordPart::CmdSubst(self.read_backquoted()?)),
+                Some('\\') => match self.next() {
+                    Some('\n') => (), // 行の継続
+                    Some(e) => parts.push(WordPart::Quoted(e.to_string())),
+                    None => return Err(self.eof_error(')')),
+                },
+                Some(c) => Word::push_lit(parts, c),
+            }
+        }
+    }
+
+    /// シングルクォートの中身を読み込む。開きクォートは読み込み済みであること。
+    fn read_single_quoted(&mut self) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+                None => return Err(self.eof_error('\'')),
+            }
+        }
+    }
+
+    /// $の後のパラメータ展開、またはコマンド置換を読み込み、partsに追加する。
+    /// $は読み込み済みであること。変数名が続かない$は文字列として扱う。
+    fn read_param(&mut self, parts: &mut Vec<WordPart>) -> Result<(), ParseError> {
+        match self.peek() {
+            Some('(') => match self.read_arith()? {
+                Some(expr) => parts.push(WordPart::Arith(expr)),
+                None => {
+                    self.next();
+                    parts.push(WordPart::CmdSubst(self.read_cmd_subst()?));
+                }
+            },
+            Some('{') => {
+                self.next();
+                parts.push(WordPart::Param(self.read_braced_param()?));
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                parts.push(WordPart::Param(Param {
+                    name: self.read_name(),
+                    index: None,
+                    op: None,
+                    braced: false,
+                }));
+            }
+            Some(c) if is_special_param(c) || c.is_ascii_digit() => {
+                // 特殊パラメータと位置パラメータは1文字。$10は$1の後に0が続くとみなす
+                self.next();
+                parts.push(WordPart::Param(Param {
+                    name: c.to_string(),
+                    index: None,
+                    op: None,
+                    braced: false,
+                }));
+            }
+            _ => Word::push_lit(parts, '$'),
+        }
+        Ok(())
+    }
+
+    /// $(の後のコマンド置換を、対応する)まで読み込み、中身のコマンド文字列を返す。
+    /// $(は読み込み済みであること。
+    ///
+    /// 中身をトークン列として読み込むことで、入れ子になったコマンド置換や、
+    /// クォートされた)を正しく扱う。
+    fn read_cmd_subst(&mut self) -> Result<String, ParseError> {
+        let start = self.idx;
+        let mut inner = Lexer {
+            chars: self.chars.clone(),
+            idx: self.idx,
+            pos: self.pos,
+            heredocs: Vec::new(),
+            extglob: self.extglob,
+        };
+        inner.read_tokens(true)?;
+        self.idx = inner.idx;
+        self.pos = inner.pos;
+        Ok(self.chars[start..self.idx - 1].iter().collect())
+    }
+
+    /// ((から始まる算術式を、対応する))まで読み込む。
+    ///
+    /// 括弧が((で始まっていない場合や、))ではなく)で閉じられている場合は、
+    /// 入れ子のサブシェルとみなし、読み込み位置を戻してNoneを返す。
+    fn read_arith(&mut self) -> Result<Option<Word>, ParseError> {
+        if self.peek() != Some('(') || self.peek_at(1) != Some('(') {
+            return Ok(None);
+        }
+        let (idx, pos) = (self.idx, self.pos);
+        self.next();
+        self.next();
+
+        let mut parts = Vec::new();
+        let mut depth = 0; // 式の中の括弧の深さ
+        loop {
+            match self.next() {
+                None => return Err(self.eof_error(')')),
+                Some('(') => {
+                    depth += 1;
+                    Word::push_lit(&mut parts, '(');
+                }
+                Some(')') if depth > 0 => {
+                    depth -= 1;
+                    Word::push_lit(&mut parts, ')');
+                }
+                Some(')') if self.peek() == Some(')') => {
+                    self.next();
+                    return Ok(Some(Word { parts }));
+                }
+                Some(')') => {
+                    self.idx = idx;
+                    self.pos = pos;
+                    return Ok(None);
+                }
+                Some('$') => self.read_param(&mut parts)?,
+                Some('`') => parts.push(WordPart::CmdSubst(self.read_backquoted()?)),
+                Some('"') => parts.push(WordPart::DQuoted(self.read_double_quoted()?)),
+                Some('\\') => match self.next() {
+                    Some('\n') => (), // 行の継続
+                    Some(e) => parts.push(WordPart::Quoted(e.to_string())),
+                    None => return Err(self.eof_error(')')),
+                },
+                Some(c) => Word::push_lit(&mut parts, c),
+            }
+        }
+    }
+
+    /// バッククォートで囲まれたコマンド置換を、閉じクォートまで読み込み、中身のコマンド文字列を返す。
+    /// 開きクォートは読み込み済みであること。
+    ///
+    /// バックスラッシュは `$`、`` ` ``、`\` の前でのみエスケープとして扱う。
+    fn read_backquoted(&mut self) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('`') => return Ok(s),
+                Some('\\') => match self.peek() {
+                    Some(e @ ('$' | '`' | '\\')) => {
+                        self.next();
+                        s.push(e);
+                    }
+                    _ => s.push('\\'),
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.eof_error('`')),
+            }
+        }
+    }
+
+    /// 変数名として使える文字の並びを読み込む。
+    fn read_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            self.next();
+        }
+        name
+    }
+
+    /// ${の後のパラメータ展開を、対応する}まで読み込む。${は読み込み済みであること。
+    fn read_braced_param(&mut self) -> Result<Param, ParseError> {
+        let start = self.idx - 2; // エラー表示用の$の位置
+        let pos = self.pos;
+
+        // ${#NAME}は長さ。${#}は位置パラメータの数
+        let length = self.peek() == Some('#') && self.peek_at(1) != Some('}');
+        if length {
+            self.next();
+        }
+
+        let name = match self.peek() {
+            Some(c) if is_special_param(c) => {
+                self.next();
+                c.to_string()
+            }
+            _ => {
+                // ${10}のように、2桁以上の位置パラメータも指定できる
+                let name = self.read_name();
+                let positional = !name.is_empty() && name.chars().all(|c| c.is_ascii_digit());
+                if !is_name(&name) && !positional {
+                    return Err(self.bad_substitution(start, pos));
+                }
+                name
+            }
+        };
+
+        let mut index = None;
+        if self.peek() == Some('[') {
+            self.next();
+            let mut s = String::new();
+            loop {
+                match self.next() {
+                    Some(']') => break,
+                    Some(c) => s.push(c),
+                    None => return Err(self.eof_error(']')),
+                }
+            }
+            index = Some(s);
+        }
+
+        let op = match self.next() {
+            Some('}') => {
+                return Ok(Param {
+                    name,
+                    index,
+                    op: length.then_some(ParamOp::Length),
+                    braced: true,
+                });
+            }
+            None => return Err(self.eof_error('}')),
+            Some(_) if length => return Err(self.bad_substitution(start, pos)),
+            Some(':') => match self.peek() {
+                Some(c @ ('-' | '=' | '?' | '+')) => {
+                    self.next();
+                    let word = self.read_brace_word(&[])?;
+                    ParamOp::Test {
+                        op: c,
+                        colon: true,
+                        word,
+                    }
+                }
+                _ => {
+                    let offset = self.read_brace_word(&[':'])?;
+                    let mut length = None;
+                    if self.peek() == Some(':') {
+                        self.next();
+                        length = Some(self.read_brace_word(&[])?);
+                    }
+                    ParamOp::Substr { offset, length }
+                }
+            },
+            Some(c @ ('-' | '=' | '?' | '+')) => ParamOp::Test {
+                op: c,
+                colon: false,
+                word: self.read_brace_word(&[])?,
+            },
+            Some(c @ ('#' | '%')) => {
+                let longest = self.peek() == Some(c);
+                if longest {
+                    self.next();
+                }
+                ParamOp::Trim {
+                    suffix: c == '%',
+                    longest,
+                    pattern: self.read_brace_word(&[])?,
+                }
+            }
+            Some('/') => {
+                let all = self.peek() == Some('/');
+                if all {
+                    self.next();
+                }
+                let pattern = self.read_brace_word(&['/'])?;
+                let mut rep = Word::default();
+                if self.peek() == Some('/') {
+                    self.next();
+                    rep = self.read_brace_word(&[])?;
+                }
+                ParamOp::Replace { all, pattern, rep }
+            }
+            Some(c @ ('^' | ',')) => {
+                let all = self.peek() == Some(c);
+                if all {
+                    self.next();
+                }
+                ParamOp::Case {
+                    upper: c == '^',
+                    all,
+                }
+            }
+            Some(_) => return Err(self.bad_substitution(start, pos)),
+        };
+
+        match self.next() {
+            Some('}') => Ok(Param {
+                name,
+                index,
+                op: Some(op),
+                braced: true,
+            }),
+            None => Err(self.eof_error('}')),
+            Some(_) => Err(self.bad_substitution(start, pos)),
+        }
+    }
+
+    /// ${...}の中の単語を、}またはstopsのいずれかの文字の直前まで読み込む。
+    fn read_brace_word(&mut self, stops: &[char]) -> Result<Word, ParseError> {
+        let mut parts = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.eof_error('}')),
+                Some(c) if c == '}' || stops.contains(&c) => return Ok(Word { parts }),
+                Some('\'') => {
+                    self.next();
+                    parts.push(WordPart::Quoted(self.read_single_quoted()?));
+                }
+                Some('"') => {
+                    self.next();
+                    parts.push(WordPart::DQuoted(self.read_double_quoted()?));
+                }
+                Some('\\') => {
+                    self.next();
+                    match self.next() {
+                        Some('\n') => (), // 行の継続
+                        Some(e) => parts.push(WordPart::Quoted(e.to_string())),
+                        None => return Err(self.eof_error('}')),
+                    }
+                }
+                Some('$') => {
+                    self.next();
+                    self.read_param(&mut parts)?;
+                }
+                Some('`') => {
+                    self.next();
+                    parts.push(WordPart::CmdSubst(self.read_backquoted()?));
+                }
+                Some(c) => {
+                    self.next();
+                    Word::push_lit(&mut parts, c);
+                }
+            }
+        }
+    }
+
+    /// 不正なパラメータ展開のエラー。対応する}まで読み飛ばし、startからの表記をエラーに含める。
+    fn bad_substitution(&mut self, start: usize, pos: Pos) -> ParseError {
+        while !matches!(self.peek(), None | Some('}')) {
+            self.next();
+        }
+        if self.next().is_none() {
+            return self.eof_error('}');
+        }
+        ParseError {
+            pos,
+            kind: ParseErrorKind::BadSubstitution(self.chars[start..self.idx].iter().collect()),
+        }
+    }
+
+    /// ダブルクォートの中身を読み込む。開きクォートは読み込み済みであること。
+    ///
+    /// バックスラッシュは `$`、`` ` ``、`"`、`\`、改行の前でのみエスケープとして扱う。
+    fn read_double_quoted(&mut self) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = Vec::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(parts),
+                Some('$') => self.read_param(&mut parts)?,
+                Some('`') => parts.push(WordPart::CmdSubst(self.read_backquoted()?)),
+                Some('\\') => match self.peek() {
+                    Some('\n') => {
+                        self.next(); // 行の継続
+                    }
+                    Some(e @ ('$' | '`' | '"' | '\\')) => {
+                        self.next();
+                        Word::push_lit(&mut parts, e);
+                    }
+                    _ => Word::push_lit(&mut parts, '\\'),
+                },
+                Some(c) => Word::push_lit(&mut parts, c),
+                None => return Err(self.eof_error('"')),
+            }
+        }
+    }
+}
+
+/// 演算子の開始文字なら真。
+fn is_operator_start(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '(' | ')' | '<' | '>' | '\n')
+}
+
+/// $?のような、1文字の特殊パラメータの名前なら真。
+fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*' | '-')
+}
+
+/// 入力文字列をトークン列に変換。extglobが真なら、?(...)などの拡張パターンも単語の一部とする。
+pub fn tokenize(input: &str, extglob: bool) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(input, extglob).tokenize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// トークンの種類の列。末尾のEofは除く。
+    fn kinds(input: &str, extglob: bool) -> Vec<TokenKind> {
+        let mut kinds: Vec<TokenKind> = tokenize(input, extglob)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(kinds.pop(), Some(TokenKind::Eof));
+        kinds
+    }
+
+    /// 要素を指定した単語のトークン。
+    fn word(parts: Vec<WordPart>) -> TokenKind {
+        TokenKind::Word(Word { parts })
+    }
+
+    /// クォートされていない文字列の要素。
+    fn lit_part(s: &str) -> WordPart {
+        WordPart::Lit(s.to_string())
+    }
+
+    /// クォートされていない文字列の単語のトークン。
+    fn lit(s: &str) -> TokenKind {
+        word(vec![lit_part(s)])
+    }
+
+    /// シングルクォート、バックスラッシュでクォートされた文字列の要素。
+    fn quoted(s: &str) -> WordPart {
+        WordPart::Quoted(s.to_string())
+    }
+
+    /// ダブルクォートで囲まれたクォートされていない文字列の要素。
+    fn dquoted(s: &str) -> WordPart {
+        WordPart::DQuoted(vec![WordPart::Lit(s.to_string())])
+    }
+
+    /// $NAMEのパラメータ展開の要素。
+    fn param(name: &str) -> WordPart {
+        WordPart::Param(Param {
+            name: name.to_string(),
+            index: None,
+            op: None,
+            braced: false,
+        })
+    }
+
+    /// リダイレクト演算子のトークン。
+    fn redir(fd: Option<i32>, op: RedirOp) -> TokenKind {
+        TokenKind::Redir { fd, op }
+    }
+
+    #[test]
+    fn quoting() {
+        let table = [
+            ("echo a   b", vec![lit("echo"), lit("a"), lit("b")]),
+            ("'a b'", vec![word(vec![quoted("a b")])]),
+            (r#"'a"\$x'"#, vec![word(vec![quoted(r#"a"\$x"#)])]),
+            ("''", vec![word(vec![quoted("")])]),
+            (r#""a 'b' c""#, vec![word(vec![dquoted("a 'b' c")])]),
+            (
+                r#""a\"b\$c\\d\e""#,
+                vec![word(vec![dquoted(r#"a"b$c\d\e"#)])],
+            ),
+            (
+                r"a\ b",
+                vec![word(vec![lit_part("a"), quoted(" "), lit_part("b")])],
+            ),
+            (r"\'\|", vec![word(vec![quoted("'"), quoted("|")])]),
+            ("a\\\nb", vec![lit("ab")]), // 行の継続
+            (
+                r#"a'b'"c"d"#,
+                vec![word(vec![
+                    lit_part("a"),
+                    quoted("b"),
+                    dquoted("c"),
+                    lit_part("d"),
+                ])],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn operators() {
+        let table = [
+            (
+                "a|b||c&&d&e;f;;g",
+                vec![
+                    lit("a"),
+                    TokenKind::Pipe,
+                    lit("b"),
+                    TokenKind::OrIf,
+                    lit("c"),
+                    TokenKind::AndIf,
+                    lit("d"),
+                    TokenKind::Amp,
+                    lit("e"),
+                    TokenKind::Semi,
+                    lit("f"),
+                    TokenKind::DSemi,
+                    lit("g"),
+                ],
+            ),
+            (
+                "(a)\nb",
+                vec![
+                    TokenKind::LParen,
+                    lit("a"),
+                    TokenKind::RParen,
+                    TokenKind::Newline,
+                    lit("b"),
+                ],
+            ),
+            (
+                "<a >b >>c <&0 >&2 &>d &>>e",
+                vec![
+                    redir(None, RedirOp::In),
+                    lit("a"),
+                    redir(None, RedirOp::Out),
+                    lit("b"),
+                    redir(None, RedirOp::Append),
+                    lit("c"),
+                    redir(None, RedirOp::DupIn),
+                    lit("0"),
+                    redir(None, RedirOp::DupOut),
+                    lit("2"),
+                    redir(None, RedirOp::OutErr),
+                    lit("d"),
+                    redir(None, RedirOp::AppendErr),
+                    lit("e"),
+                ],
+            ),
+            (
+                "2>err 10<in 1x>out '2'>q",
+                vec![
+                    redir(Some(2), RedirOp::Out),
+                    lit("err"),
+                    redir(Some(10), RedirOp::In),
+                    lit("in"),
+                    lit("1x"),
+                    redir(None, RedirOp::Out),
+                    lit("out"),
+                    word(vec![quoted("2")]),
+                    redir(None, RedirOp::Out),
+                    lit("q"),
+                ],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn comment() {
+        let table = [
+            ("echo a#b # comment", vec![lit("echo"), lit("a#b")]),
+            ("a #;b\nc", vec![lit("a"), TokenKind::Newline, lit("c")]),
+            ("#a", vec![]),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn parameter() {
+        let table = [
+            ("$x", vec![word(vec![param("x")])]),
+            (
+                r#""x$y""#,
+                vec![word(vec![WordPart::DQuoted(vec![
+                    lit_part("x"),
+                    param("y"),
+                ])])],
+            ),
+            ("'$x'", vec![word(vec![quoted("$x")])]),
+            ("a=$b", vec![word(vec![lit_part("a="), param("b")])]),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn here_doc() {
+        let table = [
+            (
+                "cat <<EOF\n$x\nEOF\n",
+                vec![
+                    lit("cat"),
+                    TokenKind::HereDoc {
+                        fd: None,
+                        strip: false,
+                        delim: Word {
+                            parts: vec![lit_part("EOF")],
+                        },
+                        body: Word {
+                            parts: vec![WordPart::DQuoted(vec![param("x"), lit_part("\n")])],
+                        },
+                    },
+                    TokenKind::Newline,
+                ],
+            ),
+            (
+                "cat 3<<-'E' | b\n\t$x\n\tE\nc",
+                vec![
+                    lit("cat"),
+                    TokenKind::HereDoc {
+                        fd: Some(3),
+                        strip: true,
+                        delim: Word {
+                            parts: vec![quoted("E")],
+                        },
+                        body: Word {
+                            parts: vec![quoted("$x\n")],
+                        },
+                    },
+                    TokenKind::Pipe,
+                    lit("b"),
+                    TokenKind::Newline,
+                    lit("c"),
+                ],
+            ),
+            (
+                "cat <<<f",
+                vec![lit("cat"), redir(None, RedirOp::HereStr), lit("f")],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+
+        let e = tokenize("cat <<EOF\na", false).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::HereDocEof("EOF".to_string()));
+        assert_eq!(e.pos, Pos { line: 2, col: 2 });
+        assert!(e.is_incomplete());
+    }
+
+    #[test]
+    fn command_substitution() {
+        let table = [
+            (
+                "echo $(a \")\" $(b))",
+                vec![
+                    lit("echo"),
+                    word(vec![WordPart::CmdSubst("a \")\" $(b)".to_string())]),
+                ],
+            ),
+            (
+                "`a b`",
+                vec![word(vec![WordPart::CmdSubst("a b".to_string())])],
+            ),
+            (
+                r#""x$(a)""#,
+                vec![word(vec![WordPart::DQuoted(vec![
+                    lit_part("x"),
+                    WordPart::CmdSubst("a".to_string()),
+                ])])],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+
+        let table = [
+            ("echo `a", ParseErrorKind::UnexpectedEof(Some('`')), (1, 8)),
+            ("echo $(a", ParseErrorKind::UnexpectedEof(Some(')')), (1, 9)),
+        ];
+        for (input, kind, (line, col)) in table {
+            let e = tokenize(input, false).unwrap_err();
+            assert_eq!(e.kind, kind, "{}", input);
+            assert_eq!(e.pos, Pos { line, col }, "{}", input);
+            assert!(e.is_incomplete(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn arith() {
+        let table = [
+            (
+                "$((1 + (2)))",
+                vec![word(vec![WordPart::Arith(Word {
+                    parts: vec![lit_part("1 + (2)")],
+                })])],
+            ),
+            (
+                "((x++))",
+                vec![TokenKind::Arith(Word {
+                    parts: vec![lit_part("x++")],
+                })],
+            ),
+            (
+                "$(($a * 2))",
+                vec![word(vec![WordPart::Arith(Word {
+                    parts: vec![param("a"), lit_part(" * 2")],
+                })])],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn unterminated() {
+        let table = [
+            (
+                "echo 'abc",
+                ParseErrorKind::UnexpectedEof(Some('\'')),
+                (1, 10),
+            ),
+            (
+                "echo \"a\nb",
+                ParseErrorKind::UnexpectedEof(Some('"')),
+                (2, 2),
+            ),
+            (
+                "echo \"a\\\"",
+                ParseErrorKind::UnexpectedEof(Some('"')),
+                (1, 10),
+            ),
+            ("echo a\\", ParseErrorKind::UnexpectedEof(None), (1, 8)),
+        ];
+        for (input, kind, (line, col)) in table {
+            let e = tokenize(input, false).unwrap_err();
+            assert_eq!(e.kind, kind, "{}", input);
+            assert_eq!(e.pos, Pos { line, col }, "{}", input);
+            assert!(e.is_incomplete(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn extglob() {
+        let table = [
+            ("echo !(x)", vec![lit("echo"), lit("!(x)")]),
+            ("ls @(a|b c).txt", vec![lit("ls"), lit("@(a|b c).txt")]),
+            ("echo +(a*(b))", vec![lit("echo"), lit("+(a*(b))")]),
+            (
+                "echo ?('x'|$y)",
+                vec![
+                    lit("echo"),
+                    word(vec![
+                        WordPart::Lit("?(".to_string()),
+                        WordPart::Quoted("x".to_string()),
+                        WordPart::Lit("|".to_string()),
+                        WordPart::Param(Param {
+                            name: "y".to_string(),
+                            index: None,
+                            op: None,
+                            braced: false,
+                        }),
+                        WordPart::Lit(")".to_string()),
+                    ]),
+                ],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, true), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn extglob_off() {
+        // extglobが無効なら、(は単語の区切りとなる
+        let table = [
+            (
+                "!(false)",
+                vec![lit("!"), TokenKind::LParen, lit("false"), TokenKind::RParen],
+            ),
+            (
+                "echo @(a|b)",
+                vec![
+                    lit("echo"),
+                    lit("@"),
+                    TokenKind::LParen,
+                    lit("a"),
+                    TokenKind::Pipe,
+                    lit("b"),
+                    TokenKind::RParen,
+                ],
+            ),
+        ];
+        for (input, expected) in table {
+            assert_eq!(kinds(input, false), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn extglob_unterminated() {
+        let e = tokenize("echo !(a|b", true).unwrap_err();
+        assert!(e.is_incomplete());
+    }
+}
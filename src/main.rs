@@ -1,5 +1,13 @@
+mod arith;
+mod brace;
+mod cond;
+mod glob;
 mod helper;
+mod lexer;
+mod parser;
+mod pattern;
 mod shell;
+mod vars;
 
 use helper::DynError;
 
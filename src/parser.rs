@@ -0,0 +1,1092 @@
+use crate::{
+    cond::{is_binary_op, is_unary_op},
+    lexer::{Pos, RedirOp, Token, TokenKind, Word, WordPart, tokenize},
+};
+use std::fmt;
+
+/// 構文エラーの種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedToken(String),     // 予期しないトークン
+    UnexpectedEof(Option<char>), // 入力が途中で終わった。Someなら対応するクォートが見つからない
+    HereDocEof(String),          // ヒアドキュメントの区切り文字列が見つからない
+    BadSubstitution(String),     // 不正なパラメータ展開
+}
+
+/// 構文エラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub pos: Pos, // エラーが発生した位置
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::UnexpectedToken(t) => {
+                write!(
+                    f,
+                    "syntax error near unexpected token `{}` at {}",
+                    t, self.pos
+                )
+            }
+            ParseErrorKind::UnexpectedEof(Some(c)) => write!(
+                f,
+                "unexpected EOF while looking for matching `{}` at {}",
+                c, self.pos
+            ),
+            ParseErrorKind::UnexpectedEof(None) => {
+                write!(f, "syntax error: unexpected end of file at {}", self.pos)
+            }
+            ParseErrorKind::HereDocEof(delim) => write!(
+                f,
+                "here-document delimited by end-of-file (wanted `{}`) at {}",
+                delim, self.pos
+            ),
+            ParseErrorKind::BadSubstitution(s) => {
+                write!(f, "{}: bad substitution at {}", s, self.pos)
+            }
+        }
+    }
+}
+
+impl ParseError {
+    /// 入力が途中で終わったことによるエラーなら真。続きの行を読み込めば解消する可能性がある。
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self.kind,
+            ParseErrorKind::UnexpectedEof(_) | ParseErrorKind::HereDocEof(_)
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// リダイレクト
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: Option<i32>, // 明示されたファイルディスクリプタ
+    pub op: RedirOp,
+    pub target: Word, // ファイル名、複製元のファイルディスクリプタ、またはヒアドキュメントの区切り文字列
+    pub heredoc: Option<Word>, // ヒアドキュメントの本文
+}
+
+/// 変数への代入。NAME=value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assign {
+    pub name: String,
+    pub value: Word,
+}
+
+impl Assign {
+    /// 単語がNAME=valueの形式なら代入に変換。
+    fn from_word(word: &Word) -> Option<Assign> {
+        let Some(WordPart::Lit(first)) = word.parts.first() else {
+            return None;
+        };
+        let (name, rest) = first.split_once('=')?;
+        if !is_name(name) {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+        if !rest.is_empty() {
+            parts.push(WordPart::Lit(rest.to_string()));
+        }
+        parts.extend(word.parts[1..].iter().cloned());
+        Some(Assign {
+            name: name.to_string(),
+            value: Word { parts },
+        })
+    }
+}
+
+/// 単純コマンド
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assigns: Vec<Assign>, // コマンド名の前に書かれた代入
+    pub words: Vec<Word>,     // コマンド名と引数
+    pub redirects: Vec<Redirect>,
+}
+
+/// caseコマンドの各節
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// [[ ]]の条件式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CondExpr {
+    Word(Word),                 // 空文字列でなければ真
+    Unary(String, Word),        // -f fileなど
+    Binary(Word, String, Word), // a == bなど。==、!=の右辺はパターン
+    Not(Box<CondExpr>),
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+}
+
+/// 複合コマンド
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompoundCommand {
+    Subshell(List), // ( list )
+    Group(List),    // { list; }
+    If {
+        conds: Vec<(List, List)>, // if/elifの条件と本体
+        else_body: Option<List>,
+    },
+    While {
+        cond: List,
+        body: List,
+        until: bool, // untilなら真
+    },
+    For {
+        var: String,
+        words: Option<Vec<Word>>, // inが省略された場合はNone
+        body: List,
+    },
+    Case {
+        word: Word,
+        arms: Vec<CaseArm>,
+    },
+    Cond(CondExpr), // [[ expr ]]
+    Arith(Word),    // (( expr ))
+}
+
+/// パイプラインを構成するコマンド
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+}
+
+/// パイプライン
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub negated: bool, // 先頭に!があれば真
+    pub cmds: Vec<Command>,
+}
+
+/// &&と||の区切り
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndOrOp {
+    And, // &&
+    Or,  // ||
+}
+
+/// &&と||で連結されたパイプライン
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+}
+
+/// リストの要素。bgが真なら&で終端されている。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub bg: bool,
+}
+
+/// ;、&、改行で区切られたコマンドの列
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+// ジョブの表示などに使うため、ASTからコマンド文字列を復元する
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        write!(f, "{}{}", self.op, self.target)
+    }
+}
+
+/// リダイレクトを空白区切りで表示。
+fn fmt_redirects(redirects: &[Redirect], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for r in redirects {
+        write!(f, " {}", r)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Assign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<String> = self
+            .assigns
+            .iter()
+            .map(|a| a.to_string())
+            .chain(self.words.iter().map(|w| w.to_string()))
+            .collect();
+        write!(f, "{}", words.join(" "))?;
+        fmt_redirects(&self.redirects, f)
+    }
+}
+
+impl fmt::Display for CondExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 優先順位の低い式が中にある場合は括弧で囲む
+        let group = |e: &CondExpr, f: &mut fmt::Formatter<'_>, and: bool| match e {
+            CondExpr::Or(..) => write!(f, "( {} )", e),
+            CondExpr::And(..) if !and => write!(f, "( {} )", e),
+            _ => write!(f, "{}", e),
+        };
+        match self {
+            CondExpr::Word(w) => write!(f, "{}", w),
+            CondExpr::Unary(op, w) => write!(f, "{} {}", op, w),
+            CondExpr::Binary(lhs, op, rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+            CondExpr::Not(e) => {
+                write!(f, "! ")?;
+                group(e, f, false)
+            }
+            CondExpr::And(lhs, rhs) => {
+                group(lhs, f, true)?;
+                write!(f, " && ")?;
+                group(rhs, f, true)
+            }
+            CondExpr::Or(lhs, rhs) => write!(f, "{} || {}", lhs, rhs),
+        }
+    }
+}
+
+impl fmt::Display for CompoundCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompoundCommand::Subshell(list) => write!(f, "( {} )", list),
+            CompoundCommand::Group(list) => write!(f, "{{ {}; }}", list),
+            CompoundCommand::If { conds, else_body } => {
+                for (i, (cond, body)) in conds.iter().enumerate() {
+                    let kw = if i == 0 { "if" } else { "; elif" };
+                    write!(f, "{} {}; then {}", kw, cond, body)?;
+                }
+                if let Some(body) = else_body {
+                    write!(f, "; else {}", body)?;
+                }
+                write!(f, "; fi")
+            }
+            CompoundCommand::While { cond, body, until } => {
+                let kw = if *until { "until" } else { "while" };
+                write!(f, "{} {}; do {}; done", kw, cond, body)
+            }
+            CompoundCommand::For { var, words, body } => {
+                write!(f, "for {}", var)?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for w in words {
+                        write!(f, " {}", w)?;
+                    }
+                }
+                write!(f, "; do {}; done", body)
+            }
+            CompoundCommand::Case { word, arms } => {
+                write!(f, "case {} in", word)?;
+                for arm in arms {
+                    let pats: Vec<String> = arm.patterns.iter().map(|w| w.to_string()).collect();
+                    write!(f, " {}) {};;", pats.join("|"), arm.body)?;
+                }
+                write!(f, " esac")
+            }
+            CompoundCommand::Cond(expr) => write!(f, "[[ {} ]]", expr),
+            CompoundCommand::Arith(expr) => write!(f, "(( {} ))", expr),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(cmd) => write!(f, "{}", cmd),
+            Command::Compound(cmd, redirects) => {
+                write!(f, "{}", cmd)?;
+                fmt_redirects(redirects, f)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        let cmds: Vec<String> = self.cmds.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", cmds.join(" | "))
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (op, pipeline) in &self.rest {
+            let op = match op {
+                AndOrOp::And => "&&",
+                AndOrOp::Or => "||",
+            };
+            write!(f, " {} {}", op, pipeline)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", item.and_or)?;
+            if item.bg {
+                write!(f, " &")?;
+            } else if i + 1 < self.items.len() {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 構文解析器
+struct Parser {
+    tokens: Vec<Token>, // 末尾は必ずEof
+    idx: usize,         // 現在の読み込み位置
+}
+
+impl Parser {
+    /// 現在位置のトークンを返す。
+    fn peek(&self) -> &Token {
+        &self.tokens[self.idx]
+    }
+
+    /// 現在位置のトークンを返し、読み込み位置を1つ進める。Eofでは進めない。
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.idx].clone();
+        if token.kind != TokenKind::Eof {
+            self.idx += 1;
+        }
+        token
+    }
+
+    /// 現在位置のトークンが予約語kwなら真。
+    fn peek_reserved(&self, kw: &str) -> bool {
+        match &self.peek().kind {
+            TokenKind::Word(w) => w.as_lit() == Some(kw),
+            _ => false,
+        }
+    }
+
+    /// 現在位置のトークンで構文エラーを生成。
+    fn error(&self) -> ParseError {
+        let token = self.peek();
+        let kind = match &token.kind {
+            TokenKind::Eof => ParseErrorKind::UnexpectedEof(None),
+            t => ParseErrorKind::UnexpectedToken(t.to_string()),
+        };
+        ParseError {
+            pos: token.pos,
+            kind,
+        }
+    }
+
+    /// 予約語kwを読み込む。それ以外ならエラー。
+    fn expect_reserved(&mut self, kw: &str) -> Result<(), ParseError> {
+        if self.peek_reserved(kw) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// 連続する改行を読み飛ばす。
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.next();
+        }
+    }
+
+    /// リストの終わりを示すトークンなら真。
+    fn at_list_end(&self) -> bool {
+        match &self.peek().kind {
+            TokenKind::Eof | TokenKind::RParen | TokenKind::DSemi => true,
+            TokenKind::Word(w) => matches!(
+                w.as_lit(),
+                Some("then" | "elif" | "else" | "fi" | "do" | "done" | "esac" | "}")
+            ),
+            _ => false,
+        }
+    }
+
+    /// リストを読み込む。
+    fn parse_list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                return Ok(List { items });
+            }
+
+            let and_or = self.parse_and_or()?;
+            let bg = match self.peek().kind {
+                TokenKind::Amp => true,
+                TokenKind::Semi | TokenKind::Newline => false,
+                _ => {
+                    items.push(ListItem { and_or, bg: false });
+                    return Ok(List { items });
+                }
+            };
+            self.next();
+            items.push(ListItem { and_or, bg });
+        }
+    }
+
+    /// 空でないリストを読み込む。
+    fn parse_nonempty_list(&mut self) -> Result<List, ParseError> {
+        let list = self.parse_list()?;
+        if list.items.is_empty() {
+            Err(self.error())
+        } else {
+            Ok(list)
+        }
+    }
+
+    /// &&、||で連結されたパイプラインを読み込む。
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::AndIf => AndOrOp::And,
+                TokenKind::OrIf => AndOrOp::Or,
+                _ => return Ok(AndOr { first, rest }),
+            };
+            self.next();
+            self.skip_newlines();
+            rest.push((op, self.parse_pipeline()?));
+        }
+    }
+
+    /// パイプラインを読み込む。
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek_reserved("!");
+        if negated {
+            self.next();
+        }
+
+        let mut cmds = vec![self.parse_command()?];
+        while self.peek().kind == TokenKind::Pipe {
+            self.next();
+            self.skip_newlines();
+            cmds.push(self.parse_command()?);
+        }
+        Ok(Pipeline { negated, cmds })
+    }
+
+    /// コマンドを1つ読み込む。
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        let compound = match &self.peek().kind {
+            TokenKind::LParen => {
+                self.next();
+                let list = self.parse_nonempty_list()?;
+                if self.peek().kind != TokenKind::RParen {
+                    return Err(self.error());
+                }
+                self.next();
+                CompoundCommand::Subshell(list)
+            }
+            TokenKind::Arith(expr) => {
+                let expr = expr.clone();
+                self.next();
+                CompoundCommand::Arith(expr)
+            }
+            TokenKind::Word(w) => match w.as_lit() {
+                Some("{") => {
+                    self.next();
+                    let list = self.parse_nonempty_list()?;
+                    self.expect_reserved("}")?;
+                    CompoundCommand::Group(list)
+                }
+                Some("if") => self.parse_if()?,
+                Some("while") => self.parse_while(false)?,
+                Some("until") => self.parse_while(true)?,
+                Some("for") => self.parse_for()?,
+                Some("case") => self.parse_case()?,
+                Some("[[") => self.parse_cond()?,
+                Some("then" | "elif" | "else" | "fi" | "do" | "done" | "esac" | "}" | "!") => {
+                    return Err(self.error());
+                }
+                _ => return self.parse_simple(),
+            },
+            TokenKind::Redir { .. } | TokenKind::HereDoc { .. } => return self.parse_simple(),
+            _ => return Err(self.error()),
+        };
+
+        let mut redirects = Vec::new();
+        while let TokenKind::Redir { .. } | TokenKind::HereDoc { .. } = self.peek().kind {
+            redirects.push(self.parse_redirect()?);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    /// 単純コマンドを読み込む。
+    fn parse_simple(&mut self) -> Result<Command, ParseError> {
+        let mut assigns = Vec::new();
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            match &self.peek().kind {
+                TokenKind::Word(w) => {
+                    // コマンド名より前のNAME=valueは代入
+                    match Assign::from_word(w) {
+                        Some(assign) if words.is_empty() => assigns.push(assign),
+                        _ => words.push(w.clone()),
+                    }
+                    self.next();
+                }
+                TokenKind::Redir { .. } | TokenKind::HereDoc { .. } => {
+                    redirects.push(self.parse_redirect()?)
+                }
+                _ => break,
+            }
+        }
+        Ok(Command::Simple(SimpleCommand {
+            assigns,
+            words,
+            redirects,
+        }))
+    }
+
+    /// リダイレクトを読み込む。
+    fn parse_redirect(&mut self) -> Result<Redirect, ParseError> {
+        let (fd, op) = match self.next().kind {
+            TokenKind::Redir { fd, op } => (fd, op),
+            TokenKind::HereDoc {
+                fd,
+                strip,
+                delim,
+                body,
+            } => {
+                let op = if strip {
+                    RedirOp::HereDocStrip
+                } else {
+                    RedirOp::HereDoc
+                };
+                return Ok(Redirect {
+                    fd,
+                    op,
+                    target: delim,
+                    heredoc: Some(body),
+                });
+            }
+            _ => unreachable!(),
+        };
+        match &self.peek().kind {
+            TokenKind::Word(w) => {
+                let target = w.clone();
+                self.next();
+                Ok(Redirect {
+                    fd,
+                    op,
+                    target,
+                    heredoc: None,
+                })
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    /// ifコマンドを読み込む。
+    fn parse_if(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("if")?;
+        let mut conds = Vec::new();
+        let mut else_body = None;
+        loop {
+            let cond = self.parse_nonempty_list()?;
+            self.expect_reserved("then")?;
+            let body = self.parse_nonempty_list()?;
+            conds.push((cond, body));
+
+            if self.peek_reserved("elif") {
+                self.next();
+                continue;
+            }
+            if self.peek_reserved("else") {
+                self.next();
+                else_body = Some(self.parse_nonempty_list()?);
+            }
+            self.expect_reserved("fi")?;
+            return Ok(CompoundCommand::If { conds, else_body });
+        }
+    }
+
+    /// while、untilコマンドを読み込む。
+    fn parse_while(&mut self, until: bool) -> Result<CompoundCommand, ParseError> {
+        self.next();
+        let cond = self.parse_nonempty_list()?;
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::While { cond, body, until })
+    }
+
+    /// do list doneを読み込む。
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_reserved("do")?;
+        let body = self.parse_nonempty_list()?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    /// forコマンドを読み込む。
+    fn parse_for(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("for")?;
+        let var = match &self.peek().kind {
+            TokenKind::Word(w) if w.as_lit().is_some_and(is_name) => w.unquote(),
+            _ => return Err(self.error()),
+        };
+        self.next();
+
+        self.skip_newlines();
+        let mut words = None;
+        if self.peek_reserved("in") {
+            self.next();
+            let mut list = Vec::new();
+            while let TokenKind::Word(w) = &self.peek().kind {
+                list.push(w.clone());
+                self.next();
+            }
+            match self.peek().kind {
+                TokenKind::Semi | TokenKind::Newline => {
+                    self.next();
+                }
+                _ => return Err(self.error()),
+            }
+            words = Some(list);
+        } else if self.peek().kind == TokenKind::Semi {
+            self.next();
+        }
+
+        self.skip_newlines();
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::For { var, words, body })
+    }
+
+    /// caseコマンドを読み込む。
+    fn parse_case(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("case")?;
+        let word = match &self.peek().kind {
+            TokenKind::Word(w) => w.clone(),
+            _ => return Err(self.error()),
+        };
+        self.next();
+        self.skip_newlines();
+        self.expect_reserved("in")?;
+
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_reserved("esac") {
+                self.next();
+                return Ok(CompoundCommand::Case { word, arms });
+            }
+
+            // パターンを読み込む
+            if self.peek().kind == TokenKind::LParen {
+                self.next();
+            }
+            let mut patterns = Vec::new();
+            loop {
+                match &self.peek().kind {
+                    TokenKind::Word(w) => patterns.push(w.clone()),
+                    _ => return Err(self.error()),
+                }
+                self.next();
+                match self.peek().kind {
+                    TokenKind::Pipe => {
+                        self.next();
+                    }
+                    TokenKind::RParen => {
+                        self.next();
+                        break;
+                    }
+                    _ => return Err(self.error()),
+                }
+            }
+
+            let body = self.parse_list()?;
+            arms.push(CaseArm { patterns, body });
+
+            match self.peek().kind {
+                TokenKind::DSemi => {
+                    self.next();
+                }
+                _ if self.peek_reserved("esac") => (),
+                _ => return Err(self.error()),
+            }
+        }
+    }
+
+    /// [[ ]]コマンドを読み込む。
+    fn parse_cond(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("[[")?;
+        let expr = self.parse_cond_or()?;
+        self.expect_reserved("]]")?;
+        Ok(CompoundCommand::Cond(expr))
+    }
+
+    /// ||で連結された条件式を読み込む。
+    fn parse_cond_or(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expr = self.parse_cond_and()?;
+        while self.peek().kind == TokenKind::OrIf {
+            self.next();
+            self.skip_newlines();
+            expr = CondExpr::Or(Box::new(expr), Box::new(self.parse_cond_and()?));
+        }
+        Ok(expr)
+    }
+
+    /// &&で連結された条件式を読み込む。
+    fn parse_cond_and(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expr = self.parse_cond_not()?;
+        while self.peek().kind == TokenKind::AndIf {
+            self.next();
+            self.skip_newlines();
+            expr = CondExpr::And(Box::new(expr), Box::new(self.parse_cond_not()?));
+        }
+        Ok(expr)
+    }
+
+    /// 先頭に!が付いた条件式を読み込む。
+    fn parse_cond_not(&mut self) -> Result<CondExpr, ParseError> {
+        if self.peek_reserved("!") {
+            self.next();
+            return Ok(CondExpr::Not(Box::new(self.parse_cond_not()?)));
+        }
+        self.parse_cond_primary()
+    }
+
+    /// 括弧で囲まれた条件式、単項演算子、二項演算子、または1つの単語を読み込む。
+    fn parse_cond_primary(&mut self) -> Result<CondExpr, ParseError> {
+        if self.peek().kind == TokenKind::LParen {
+            self.next();
+            let expr = self.parse_cond_or()?;
+            if self.peek().kind != TokenKind::RParen {
+                return Err(self.error());
+            }
+            self.next();
+            return Ok(expr);
+        }
+
+        let word = match &self.peek().kind {
+            TokenKind::Word(w) if w.as_lit() != Some("]]") => w.clone(),
+            _ => return Err(self.error()),
+        };
+        self.next();
+
+        // 単項演算子。[[ -n ]]のように引数がなければ文字列とみなす
+        if let Some(op) = word.as_lit().filter(|op| is_unary_op(op))
+            && let TokenKind::Word(arg) = &self.peek().kind
+            && arg.as_lit() != Some("]]")
+        {
+            let expr = CondExpr::Unary(op.to_string(), arg.clone());
+            self.next();
+            return Ok(expr);
+        }
+
+        // 二項演算子。<と>はリダイレクトとして字句解析される
+        let op = match &self.peek().kind {
+            TokenKind::Word(w) => w.as_lit().filter(|op| is_binary_op(op)),
+            TokenKind::Redir {
+                fd: None,
+                op: RedirOp::In,
+            } => Some("<"),
+            TokenKind::Redir {
+                fd: None,
+                op: RedirOp::Out,
+            } => Some(">"),
+            _ => None,
+        };
+        let Some(op) = op.map(String::from) else {
+            return Ok(CondExpr::Word(word));
+        };
+        self.next();
+        match &self.peek().kind {
+            TokenKind::Word(rhs) => {
+                let expr = CondExpr::Binary(word, op, rhs.clone());
+                self.next();
+                Ok(expr)
+            }
+            _ => Err(self.error()),
+        }
+    }
+}
+
+/// 変数名として正しい文字列なら真。
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// コマンドライン全体をパース。extglobが真なら、?(...)などの拡張パターンも単語の一部とする。
+pub fn parse(input: &str, extglob: bool) -> Result<List, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(input, extglob)?,
+        idx: 0,
+    };
+    let list = parser.parse_list()?;
+    if parser.peek().kind != TokenKind::Eof {
+        return Err(parser.error());
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// クォートされていない文字列の単語。
+    fn word(s: &str) -> Word {
+        Word {
+            parts: vec![WordPart::Lit(s.to_string())],
+        }
+    }
+
+    /// 空白区切りの単語からなる単純コマンド。
+    fn simple(s: &str) -> Command {
+        Command::Simple(SimpleCommand {
+            assigns: Vec::new(),
+            words: s.split_whitespace().map(word).collect(),
+            redirects: Vec::new(),
+        })
+    }
+
+    /// 単純コマンドを|で連結したパイプライン。
+    fn pipeline(negated: bool, cmds: &[&str]) -> Pipeline {
+        Pipeline {
+            negated,
+            cmds: cmds.iter().map(|s| simple(s)).collect(),
+        }
+    }
+
+    /// 1つのパイプラインからなるリストの要素。
+    fn item(cmds: &[&str], bg: bool) -> ListItem {
+        ListItem {
+            and_or: AndOr {
+                first: pipeline(false, cmds),
+                rest: Vec::new(),
+            },
+            bg,
+        }
+    }
+
+    #[test]
+    fn list() {
+        let table = [
+            ("", vec![]),
+            ("a", vec![item(&["a"], false)]),
+            (
+                "a; b & c",
+                vec![item(&["a"], false), item(&["b"], true), item(&["c"], false)],
+            ),
+            (
+                "\na x\n\nb &\n",
+                vec![item(&["a x"], false), item(&["b"], true)],
+            ),
+            ("a | b c | d &", vec![item(&["a", "b c", "d"], true)]),
+        ];
+        for (input, items) in table {
+            assert_eq!(parse(input, false), Ok(List { items }), "{}", input);
+        }
+    }
+
+    #[test]
+    fn and_or() {
+        let list = parse("! a | b && c ||\n d", false).unwrap();
+        assert_eq!(
+            list.items,
+            vec![ListItem {
+                and_or: AndOr {
+                    first: pipeline(true, &["a", "b"]),
+                    rest: vec![
+                        (AndOrOp::And, pipeline(false, &["c"])),
+                        (AndOrOp::Or, pipeline(false, &["d"])),
+                    ],
+                },
+                bg: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn redirect() {
+        let list = parse("a 2>err b <in >>out", false).unwrap();
+        let Command::Simple(cmd) = &list.items[0].and_or.first.cmds[0] else {
+            panic!("{:?}", list);
+        };
+        assert_eq!(cmd.words, vec![word("a"), word("b")]);
+        let redirects: Vec<_> = cmd
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.op, r.target.clone()))
+            .collect();
+        assert_eq!(
+            redirects,
+            vec![
+                (Some(2), RedirOp::Out, word("err")),
+                (None, RedirOp::In, word("in")),
+                (None, RedirOp::Append, word("out")),
+            ]
+        );
+
+        // 複合コマンドの後のリダイレクトは複合コマンド全体に適用する
+        let list = parse("{ a; } >out | b", false).unwrap();
+        let Command::Compound(CompoundCommand::Group(body), redirects) =
+            &list.items[0].and_or.first.cmds[0]
+        else {
+            panic!("{:?}", list);
+        };
+        assert_eq!(body.items, vec![item(&["a"], false)]);
+        assert_eq!(redirects.len(), 1);
+        assert_eq!(redirects[0].target, word("out"));
+    }
+
+    #[test]
+    fn compound() {
+        let list = parse("if a; then b; elif c\nthen d; else e; fi", false).unwrap();
+        assert_eq!(
+            list.items[0].and_or.first.cmds[0],
+            Command::Compound(
+                CompoundCommand::If {
+                    conds: vec![
+                        (
+                            List {
+                                items: vec![item(&["a"], false)]
+                            },
+                            List {
+                                items: vec![item(&["b"], false)]
+                            },
+                        ),
+                        (
+                            List {
+                                items: vec![item(&["c"], false)]
+                            },
+                            List {
+                                items: vec![item(&["d"], false)]
+                            },
+                        ),
+                    ],
+                    else_body: Some(List {
+                        items: vec![item(&["e"], false)]
+                    }),
+                },
+                Vec::new(),
+            )
+        );
+
+        // 表示した文字列で構造を確認する
+        let table = [
+            ("(a; b) | c", "( a; b ) | c"),
+            ("{ a\nb & }", "{ a; b &; }"),
+            ("while a; do b; done", "while a; do b; done"),
+            ("until a\ndo\nb\ndone", "until a; do b; done"),
+            ("for x in a b; do c; done", "for x in a b; do c; done"),
+            ("for x\ndo c; done", "for x; do c; done"),
+            ("for x in; do c; done", "for x in; do c; done"),
+            (
+                "case x in a|b) c;; (d) e; f;; *) ;; esac",
+                "case x in a|b) c;; d) e; f;; *) ;; esac",
+            ),
+            ("case x in\na) b\nesac", "case x in a) b;; esac"),
+            ("if a; then b; fi && c", "if a; then b; fi && c"),
+            ("echo if then fi", "echo if then fi"),
+        ];
+        for (input, expected) in table {
+            assert_eq!(parse(input, false).unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn error() {
+        let table = [
+            (
+                ")",
+                ParseErrorKind::UnexpectedToken(")".to_string()),
+                (1, 1),
+            ),
+            (
+                "a && && b",
+                ParseErrorKind::UnexpectedToken("&&".to_string()),
+                (1, 6),
+            ),
+            (
+                "a; ;",
+                ParseErrorKind::UnexpectedToken(";".to_string()),
+                (1, 4),
+            ),
+            (
+                "echo > ;",
+                ParseErrorKind::UnexpectedToken(";".to_string()),
+                (1, 8),
+            ),
+            (
+                "fi",
+                ParseErrorKind::UnexpectedToken("fi".to_string()),
+                (1, 1),
+            ),
+            (
+                "{ }",
+                ParseErrorKind::UnexpectedToken("}".to_string()),
+                (1, 3),
+            ),
+            (
+                "for 1 in a; do b; done",
+                ParseErrorKind::UnexpectedToken("1".to_string()),
+                (1, 5),
+            ),
+            (
+                "(a) b",
+                ParseErrorKind::UnexpectedToken("b".to_string()),
+                (1, 5),
+            ),
+        ];
+        for (input, kind, (line, col)) in table {
+            let e = parse(input, false).unwrap_err();
+            assert_eq!(e.kind, kind, "{}", input);
+            assert_eq!(e.pos, Pos { line, col }, "{}", input);
+            assert!(!e.is_incomplete(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn incomplete() {
+        // 続きの行を読み込めば解消する可能性があるエラー
+        let table = [
+            ("a |", (1, 4)),
+            ("a &&\n", (2, 1)),
+            ("echo >", (1, 7)),
+            ("(a", (1, 3)),
+            ("if a; then b", (1, 13)),
+            ("while a\ndo b", (2, 5)),
+            ("for x in a b", (1, 13)),
+            ("case x in a) b;;", (1, 17)),
+        ];
+        for (input, (line, col)) in table {
+            let e = parse(input, false).unwrap_err();
+            assert_eq!(e.kind, ParseErrorKind::UnexpectedEof(None), "{}", input);
+            assert_eq!(e.pos, Pos { line, col }, "{}", input);
+            assert!(e.is_incomplete(), "{}", input);
+        }
+    }
+}
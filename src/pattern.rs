@@ -0,0 +1,593 @@
+/// ブラケット式の要素
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClassItem {
+    Char(char),        // 1文字
+    Range(char, char), // a-zのような範囲
+    Named(String),     // [:alpha:]のような文字クラス
+}
+
+/// パターンの構成要素
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatToken {
+    Char(char), // 1文字
+    Any,        // ?
+    Star,       // *
+    Class {
+        negated: bool, // [!...]または[^...]なら真
+        items: Vec<ClassItem>,
+    },
+    Ext {
+        op: char,                 // ?、*、+、@、!のいずれか
+        alts: Vec<Vec<PatToken>>, // |で区切られたパターン
+    },
+}
+
+/// シェルのパターン。*、?、[...]と、extglobの?(...)、*(...)、+(...)、@(...)、!(...)に対応。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    tokens: Vec<PatToken>,
+    nocase: bool, // 大文字と小文字を区別しないなら真
+}
+
+impl Pattern {
+    /// パターン文字列を解釈。バックスラッシュでエスケープされた文字はリテラルとして扱う。
+    /// extglobが真なら、?(...)などの拡張パターンも解釈する。
+    pub fn new(pat: &str, extglob: bool) -> Self {
+        let chars: Vec<char> = pat.chars().collect();
+        Pattern {
+            tokens: parse_tokens(&chars, extglob),
+            nocase: false,
+        }
+    }
+
+    /// onが真なら、大文字と小文字を区別せずにマッチするようにする。
+    pub fn nocase(mut self, on: bool) -> Self {
+        self.nocase = on;
+        self
+    }
+
+    /// *、?、[...]を含まず、文字列としてのみマッチするなら真。
+    pub fn is_literal(&self) -> bool {
+        self.tokens.iter().all(|t| matches!(t, PatToken::Char(_)))
+    }
+
+    /// 文字列全体がパターンにマッチすれば真。
+    pub fn matches(&self, s: &str) -> bool {
+        let chars: Vec<char> = s.chars().collect();
+        if self
+            .tokens
+            .iter()
+            .any(|t| matches!(t, PatToken::Ext { .. }))
+        {
+            return self.match_seq(&self.tokens, &chars, 0, &|end| end == chars.len());
+        }
+
+        // 最後に現れた*の位置と、その*がマッチした文字列の終わりを記録してバックトラック
+        let (mut p, mut c) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while c < chars.len() {
+            match self.tokens.get(p) {
+                Some(PatToken::Star) => {
+                    star = Some((p, c));
+                    p += 1;
+                    continue;
+                }
+                Some(t) if t.matches_char(chars[c], self.nocase) => {
+                    p += 1;
+                    c += 1;
+                    continue;
+                }
+                _ => (),
+            }
+
+            // マッチしなかったので、直前の*にもう1文字マッチさせる
+            match star {
+                Some((sp, sc)) => {
+                    star = Some((sp, sc + 1));
+                    p = sp + 1;
+                    c = sc + 1;
+                }
+                None => return false,
+            }
+        }
+
+        self.tokens[p..].iter().all(|t| *t == PatToken::Star)
+    }
+
+    /// 拡張パターンを含むパターンのマッチ。toksがchars[i..]の先頭部分にマッチするごとに、
+    /// その終わりの位置でkを呼び出し、kが真を返せば真を返す。
+    fn match_seq(
+        &self,
+        toks: &[PatToken],
+        chars: &[char],
+        i: usize,
+        k: &dyn Fn(usize) -> bool,
+    ) -> bool {
+        let Some((tok, rest)) = toks.split_first() else {
+            return k(i);
+        };
+        let next = |end: usize| self.match_seq(rest, chars, end, k);
+        match tok {
+            PatToken::Star => (i..=chars.len()).any(next),
+            PatToken::Ext { op: '!', alts } => {
+                // いずれのパターンにもマッチしない部分
+                (i..=chars.len()).any(|end| {
+                    let hit = alts
+                        .iter()
+                        .any(|alt| self.match_seq(alt, chars, i, &|e| e == end));
+                    !hit && next(end)
+                })
+            }
+            PatToken::Ext { op, alts } => match op {
+                '@' => self.match_alts(alts, chars, i, &next),
+                '?' => next(i) || self.match_alts(alts, chars, i, &next),
+                '*' => self.match_repeat(alts, chars, i, &next),
+                _ => self.match_alts(alts, chars, i, &|e| {
+                    self.match_repeat(alts, chars, e, &next)
+                }),
+            },
+            t => i < chars.len() && t.matches_char(chars[i], self.nocase) && next(i + 1),
+        }
+    }
+
+    /// altsのいずれかのパターンがchars[i..]の先頭部分にマッチするごとに、kを呼び出す。
+    fn match_alts(
+        &self,
+        alts: &[Vec<PatToken>],
+        chars: &[char],
+        i: usize,
+        k: &dyn Fn(usize) -> bool,
+    ) -> bool {
+        alts.iter().any(|alt| self.match_seq(alt, chars, i, k))
+    }
+
+    /// altsのいずれかのパターンの0回以上の繰り返しがchars[i..]の先頭部分にマッチするごとに、
+    /// kを呼び出す。空文字列へのマッチは繰り返さず、無限の繰り返しを防ぐ。
+    fn match_repeat(
+        &self,
+        alts: &[Vec<PatToken>],
+        chars: &[char],
+        i: usize,
+        k: &dyn Fn(usize) -> bool,
+    ) -> bool {
+        k(i) || self.match_alts(alts, chars, i, &|e| {
+            e > i && self.match_repeat(alts, chars, e, k)
+        })
+    }
+
+    /// sの先頭（suffixが真なら末尾）からパターンにマッチする部分を取り除く。
+    /// longestが真なら最長一致、偽なら最短一致の部分を取り除く。${V#pat}などで使用。
+    pub fn strip(&self, s: &str, suffix: bool, longest: bool) -> String {
+        let chars: Vec<char> = s.chars().collect();
+        let text = |r: std::ops::Range<usize>| chars[r].iter().collect::<String>();
+
+        // 取り除く文字数の候補を、短い順または長い順に並べる
+        let mut lens: Vec<usize> = (0..=chars.len()).collect();
+        if longest {
+            lens.reverse();
+        }
+        for n in lens {
+            if suffix {
+                let cut = chars.len() - n;
+                if self.matches(&text(cut..chars.len())) {
+                    return text(0..cut);
+                }
+            } else if self.matches(&text(0..n)) {
+                return text(n..chars.len());
+            }
+        }
+        s.to_string()
+    }
+
+    /// sの中でパターンに最長一致する部分をrepに置換。
+    /// allが真なら全ての一致を、偽なら最初の一致のみを置換する。${V/pat/rep}などで使用。
+    pub fn replace(&self, s: &str, rep: &str, all: bool) -> String {
+        let chars: Vec<char> = s.chars().collect();
+        let mut result = String::new();
+        let mut replaced = false;
+        let mut i = 0;
+        while i < chars.len() {
+            if all || !replaced {
+                // 空文字列には一致させない
+                let end = (i + 1..=chars.len())
+                    .rev()
+                    .find(|&j| self.matches(&chars[i..j].iter().collect::<String>()));
+                if let Some(end) = end {
+                    result.push_str(rep);
+                    replaced = true;
+                    i = end;
+                    continue;
+                }
+            }
+            result.push(chars[i]);
+            i += 1;
+        }
+        result
+    }
+}
+
+/// パターン文字列を構成要素に分割。
+fn parse_tokens(chars: &[char], extglob: bool) -> Vec<PatToken> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        // ?(a|b)のような拡張パターン
+        if extglob
+            && matches!(chars[i], '?' | '*' | '+' | '@' | '!')
+            && chars.get(i + 1) == Some(&'(')
+            && let Some((alts, next)) = parse_ext(chars, i + 2)
+        {
+            let alts = alts.iter().map(|alt| parse_tokens(alt, extglob)).collect();
+            tokens.push(PatToken::Ext { op: chars[i], alts });
+            i = next;
+            continue;
+        }
+
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(PatToken::Char(chars[i + 1]));
+                i += 2;
+            }
+            '?' => {
+                tokens.push(PatToken::Any);
+                i += 1;
+            }
+            '*' => {
+                // 連続する*は1つと同じ
+                if tokens.last() != Some(&PatToken::Star) {
+                    tokens.push(PatToken::Star);
+                }
+                i += 1;
+            }
+            '[' => match parse_class(chars, i + 1) {
+                Some((token, next)) => {
+                    tokens.push(token);
+                    i = next;
+                }
+                None => {
+                    // 閉じられていない[はリテラル
+                    tokens.push(PatToken::Char('['));
+                    i += 1;
+                }
+            },
+            c => {
+                tokens.push(PatToken::Char(c));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// (の直後から始まる拡張パターンの中身を|で区切り、)の次の位置とともに返す。
+/// 閉じられていない場合はNoneを返す。
+fn parse_ext(chars: &[char], mut i: usize) -> Option<(Vec<&[char]>, usize)> {
+    let mut alts = Vec::new();
+    let mut start = i;
+    let mut depth = 0; // 入れ子になった括弧の深さ
+    loop {
+        match *chars.get(i)? {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' => {
+                alts.push(&chars[start..i]);
+                return Some((alts, i + 1));
+            }
+            '|' if depth == 0 => {
+                alts.push(&chars[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+}
+
+impl PatToken {
+    /// *以外の要素が1文字cにマッチすれば真。nocaseが真なら大文字と小文字を区別しない。
+    fn matches_char(&self, c: char, nocase: bool) -> bool {
+        if nocase {
+            let (lower, upper) = (to_lower(c), to_upper(c));
+            return self.matches_char(lower, false) || self.matches_char(upper, false);
+        }
+        match self {
+            PatToken::Char(x) => *x == c,
+            PatToken::Any => true,
+            PatToken::Star | PatToken::Ext { .. } => false,
+            PatToken::Class { negated, items } => {
+                let hit = items.iter().any(|item| match item {
+                    ClassItem::Char(x) => *x == c,
+                    ClassItem::Range(lo, hi) => *lo <= c && c <= *hi,
+                    ClassItem::Named(name) => class_matches(name, c),
+                });
+                hit != *negated
+            }
+        }
+    }
+}
+
+/// 1文字の小文字への変換。複数の文字になる場合は変換しない。
+fn to_lower(c: char) -> char {
+    let mut it = c.to_lowercase();
+    match (it.next(), it.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+/// 1文字の大文字への変換。複数の文字になる場合は変換しない。
+fn to_upper(c: char) -> char {
+    let mut it = c.to_uppercase();
+    match (it.next(), it.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+/// [:name:]の文字クラスに文字cが含まれれば真。存在しないクラスには何もマッチしない。
+fn class_matches(name: &str, c: char) -> bool {
+    match name {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "cntrl" => c.is_control(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+/// [の直後から始まるブラケット式を解釈し、要素と]の次の位置を返す。
+/// 閉じられていない場合はNoneを返す。
+fn parse_class(chars: &[char], mut i: usize) -> Option<(PatToken, usize)> {
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut items = Vec::new();
+    let start = i;
+    loop {
+        // [:alpha:]のような文字クラス
+        if chars.get(i) == Some(&'[')
+            && chars.get(i + 1) == Some(&':')
+            && let Some(len) = chars[i + 2..].windows(2).position(|w| w == [':', ']'])
+        {
+            items.push(ClassItem::Named(chars[i + 2..i + 2 + len].iter().collect()));
+            i += len + 4;
+            continue;
+        }
+
+        let c = match *chars.get(i)? {
+            ']' if i > start => return Some((PatToken::Class { negated, items }, i + 1)),
+            '\\' => {
+                i += 1;
+                *chars.get(i)?
+            }
+            c => c,
+        };
+        i += 1;
+
+        // a-zのような範囲。-が末尾にある場合はリテラル
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&n| n != ']') {
+            let mut hi = chars[i + 1];
+            i += 2;
+            if hi == '\\' {
+                hi = *chars.get(i)?;
+                i += 1;
+            }
+            items.push(ClassItem::Range(c, hi));
+        } else {
+            items.push(ClassItem::Char(c));
+        }
+    }
+}
+
+/// escapeでエスケープされた文字列を元に戻す。
+pub fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// パターンの特殊文字をエスケープし、リテラルとしてマッチするようにする。
+/// 拡張パターンの括弧と|もエスケープする。
+pub fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\' | '(' | ')' | '|') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// パターンpatに、表の各文字列がマッチするかを検査。
+    fn check(pat: &str, extglob: bool, table: &[(&str, bool)]) {
+        let pattern = Pattern::new(pat, extglob);
+        for (s, expected) in table {
+            assert_eq!(pattern.matches(s), *expected, "{} {}", pat, s);
+        }
+    }
+
+    #[test]
+    fn wildcard() {
+        check(
+            "*",
+            false,
+            &[("", true), ("abc", true), ("a/b", true), (".x", true)],
+        );
+        check(
+            "a*c",
+            false,
+            &[("ac", true), ("abbc", true), ("acb", false), ("bac", false)],
+        );
+        check(
+            "*.rs",
+            false,
+            &[("main.rs", true), (".rs", true), ("main.rs.bak", false)],
+        );
+        check(
+            "a?c",
+            false,
+            &[("abc", true), ("a.c", true), ("ac", false), ("abbc", false)],
+        );
+        check("??", false, &[("ab", true), ("あい", true), ("a", false)]);
+        check("*a*a*", false, &[("banana", true), ("bnn", false)]);
+    }
+
+    #[test]
+    fn bracket() {
+        check(
+            "[abc]",
+            false,
+            &[("a", true), ("c", true), ("d", false), ("ab", false)],
+        );
+        check(
+            "[a-c0-9]x",
+            false,
+            &[("bx", true), ("5x", true), ("dx", false)],
+        );
+        for pat in ["[!a-c]", "[^a-c]"] {
+            check(
+                pat,
+                false,
+                &[("a", false), ("c", false), ("d", true), ("-", true)],
+            );
+        }
+        check("[]a]", false, &[("]", true), ("a", true), ("b", false)]);
+        check("[!]]", false, &[("]", false), ("a", true)]);
+        check("[a-]", false, &[("a", true), ("-", true), ("b", false)]);
+        check("[ab", false, &[("[ab", true), ("a", false)]); // 閉じられていない[は文字として扱う
+    }
+
+    #[test]
+    fn escaped() {
+        check(r"\*", false, &[("*", true), ("a", false)]);
+        check(r"a\?", false, &[("a?", true), ("ab", false)]);
+        check(r"\[a]", false, &[("[a]", true), ("a", false)]);
+        check(r"[\]]", false, &[("]", true), ("\\", false)]);
+        check(r"\\*", false, &[(r"\x", true), ("x", false)]);
+
+        // escapeしたパターンは、元の文字列のみにマッチする
+        let s = r"*a?[b]\c";
+        check(&escape(s), false, &[(s, true), (r"xa?[b]\c", false)]);
+    }
+
+    #[test]
+    fn strip_and_replace() {
+        let pattern = Pattern::new("*/", false);
+        assert_eq!(pattern.strip("a/b/c", false, false), "b/c");
+        assert_eq!(pattern.strip("a/b/c", false, true), "c");
+
+        let pattern = Pattern::new(".*", false);
+        assert_eq!(pattern.strip("a.tar.gz", true, false), "a.tar");
+        assert_eq!(pattern.strip("a.tar.gz", true, true), "a");
+        assert_eq!(pattern.strip("abc", true, true), "abc");
+
+        let pattern = Pattern::new("a*", false);
+        assert_eq!(pattern.replace("banana", "X", false), "bX");
+        let pattern = Pattern::new("a", false);
+        assert_eq!(pattern.replace("banana", "o", false), "bonana");
+        assert_eq!(pattern.replace("banana", "o", true), "bonono");
+    }
+
+    #[test]
+    fn char_class() {
+        check(
+            "[[:digit:][:upper:]]",
+            false,
+            &[("7", true), ("Q", true), ("q", false)],
+        );
+        check("[![:space:]]", false, &[(" ", false), ("x", true)]);
+        check("[[:alpha:]_]*", false, &[("_a1", true), ("1a", false)]);
+    }
+
+    #[test]
+    fn nocase() {
+        let pattern = Pattern::new("a*[x-z]", false).nocase(true);
+        assert!(pattern.matches("ABY"));
+        assert!(pattern.matches("aby"));
+        assert!(!pattern.matches("abw"));
+        assert!(!Pattern::new("a", false).matches("A"));
+    }
+
+    #[test]
+    fn literal() {
+        // 特殊文字を含まないパターンは、ディレクトリを読まずにパス名として扱える
+        let s = r"*a?[b]\c";
+        assert!(Pattern::new(&escape(s), false).is_literal());
+        assert_eq!(unescape(&escape(s)), s);
+        assert!(Pattern::new("abc", false).is_literal());
+        assert!(!Pattern::new(s, false).is_literal());
+        assert!(!Pattern::new("[a]", false).is_literal());
+    }
+
+    #[test]
+    fn extglob() {
+        check(
+            "!(*.txt)",
+            true,
+            &[
+                ("a.rs", true),
+                ("a.txt", false),
+                ("", true),
+                (".txt", false),
+            ],
+        );
+        check(
+            "!(a|b)",
+            true,
+            &[("a", false), ("b", false), ("ab", true), ("c", true)],
+        );
+        check(
+            "x!(y)z",
+            true,
+            &[("xz", true), ("xyz", false), ("xyyz", true)],
+        );
+        check(
+            "?(a|b)c",
+            true,
+            &[("c", true), ("ac", true), ("abc", false)],
+        );
+        check("*(ab)", true, &[("", true), ("abab", true), ("aba", false)]);
+        check(
+            "+(ab|c)",
+            true,
+            &[("", false), ("abcab", true), ("abd", false)],
+        );
+        check(
+            "@(foo|ba[rz])",
+            true,
+            &[("foo", true), ("baz", true), ("ba", false)],
+        );
+        check("+(a*(b))", true, &[("abbab", true), ("ba", false)]);
+
+        // extglobが無効なら、括弧は文字として扱う
+        check("!(a)", false, &[("!(a)", true), ("b", false)]);
+
+        // escapeしたパターンは、extglobが有効でも元の文字列のみにマッチする
+        let s = r"@(a|b)\c";
+        check(&escape(s), true, &[(s, true), ("a", false)]);
+        assert!(Pattern::new(&escape(s), true).is_literal());
+        assert!(!Pattern::new(s, true).is_literal());
+    }
+}
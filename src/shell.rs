@@ -1,20 +1,45 @@
-use crate::helper::DynError;
+use crate::{
+    arith, brace, cond,
+    glob::{self, GlobOptions},
+    helper::DynError,
+    lexer::{Param, ParamOp, RedirOp, Word, WordPart},
+    parser::{
+        AndOr, AndOrOp, CaseArm, Command, CompoundCommand, CondExpr, List, Pipeline, Redirect,
+        SimpleCommand, is_name, parse,
+    },
+    pattern::{self, Pattern},
+    vars::{Var, Vars},
+};
 use nix::{
+    fcntl::{FcntlArg, OFlag, fcntl, open},
     libc,
     sys::{
-        signal::{SigHandler, Signal, killpg, signal},
+        signal::{SigHandler, Signal, kill, killpg, signal},
+        stat::Mode,
+        termios::{SetArg, Termios, tcgetattr, tcsetattr},
         wait::{WaitPidFlag, WaitStatus, waitpid},
     },
-    unistd::{self, ForkResult, Pid, dup2, execvp, fork, pipe, setpgid, tcgetpgrp, tcsetpgrp},
+    unistd::{
+        self, AccessFlags, ForkResult, Pid, access, dup2, execve, fork, pipe2, setpgid, tcgetpgrp,
+        tcsetpgrp,
+    },
 };
-use rustyline::{Editor, error::ReadlineError};
+use rustyline::{Editor, ExternalPrinter, error::ReadlineError};
 use signal_hook::{consts::*, iterator::Signals};
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     ffi::CString,
+    fmt,
+    fs::File,
+    io::{Read, Write},
     mem::replace,
+    os::unix::io::{FromRawFd, RawFd},
     process::exit,
-    sync::mpsc::{Receiver, Sender, SyncSender, channel, sync_channel},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, SyncSender, channel, sync_channel},
+    },
     thread,
 };
 
@@ -65,21 +90,24 @@ impl Shell {
             eprintln!("ZeroSh: ヒストリファイルの読み込みに失敗: {}", e);
         };
 
+        // ジョブの状態変化の通知と、shopt extglobの設定。workerスレッドと共有する
+        let notifier = Notifier::default();
+        let extglob = Arc::new(AtomicBool::new(false));
+
         // チャネルを生成し、signal_handlerとworkerスレッドを生成
         let (worker_tx, worker_rx) = channel();
         let (shell_tx, shell_rx) = sync_channel(0);
         spawn_sig_handler(worker_tx.clone())?;
-        Worker::new().spawn(worker_rx, shell_tx);
+        Worker::new(notifier.clone(), Arc::clone(&extglob)).spawn(worker_rx, shell_tx);
 
         let exit_val; // 終了コード
         let mut prev = 0; // 直前の終了コード
         loop {
             use signal_hook::consts::signal::SIGTSTP;
             use signal_hook::iterator::Signals;
-            use std::sync::{Arc, Mutex};
             let worker_tx = Arc::new(Mutex::new(worker_tx.clone()));
 
-            let mut signals = Signals::new(&[SIGTSTP]).unwrap();
+            let mut signals = Signals::new([SIGTSTP]).unwrap();
             let worker_tx_clone = Arc::clone(&worker_tx);
 
             std::thread::spawn(move || {
@@ -95,6 +123,12 @@ impl Shell {
                 }
             });
 
+            // 前回のプロンプト以降に溜まったジョブの状態変化を表示
+            for (_, msg) in notifier.queue.lock().unwrap().drain(..) {
+                eprintln!("{}", msg);
+            }
+            notifier.update_printer(&mut rl);
+
             // 1行読み込んで、その行をworkerスレッドに送信
             let face = if prev == 0 { '\u{1F982}' } else { '\u{1F4A9}' }; // prompt
             let worker_tx_clone = Arc::clone(&worker_tx);
@@ -103,10 +137,16 @@ impl Shell {
                     let line_trimed = line.trim(); // 行頭と行まつの空白文字を削除
                     if line_trimed.is_empty() {
                         continue; // 空のコマンドの場合は再読み込み
-                    } else {
-                        rl.add_history_entry(line_trimed); // ヒストリファイルに追加
                     }
 
+                    // ヒアドキュメントや閉じられていないクォートなど、
+                    // 入力が途中で終わっている場合は続きの行を読み込む
+                    let line = match read_continuation(&mut rl, line, &extglob) {
+                        Some(line) => line,
+                        None => continue,
+                    };
+                    rl.add_history_entry(line.trim()); // ヒストリファイルに追加
+
                     // workerスレッドに送信
                     worker_tx_clone
                         .lock()
@@ -130,12 +170,12 @@ impl Shell {
                         .send(WorkerMsg::Cmd("exit".to_string()))
                         .unwrap();
                     match shell_rx.recv().unwrap() {
+                        ShellMsg::Continue(n) => prev = n, // ジョブがあるため終了しなかった
                         ShellMsg::Quit(n) => {
                             // シェルを終了
                             exit_val = n;
                             break;
                         }
-                        _ => panic!("exitに失敗"),
                     }
                 }
                 Err(e) => {
@@ -153,9 +193,36 @@ impl Shell {
     }
 }
 
+/// 入力が完結するまで続きの行を読み込み、入力全体を返す。
+/// 読み込みを中断した場合はNoneを返す。
+///
+/// extglobはworkerスレッドが更新するshopt extglobの設定で、無効なら?(...)などを拡張パターンとしない。
+fn read_continuation(
+    rl: &mut Editor<()>,
+    mut line: String,
+    extglob: &AtomicBool,
+) -> Option<String> {
+    loop {
+        match parse(&line, extglob.load(Ordering::SeqCst)) {
+            Err(e) if e.is_incomplete() => match rl.readline("> ") {
+                Ok(next) => {
+                    line.push('\n');
+                    line.push_str(&next);
+                }
+                Err(ReadlineError::Eof) => {
+                    eprintln!("ZeroSh: {}", e);
+                    return None;
+                }
+                Err(_) => return None,
+            },
+            _ => return Some(line), // 構文エラーはworkerスレッドで表示
+        }
+    }
+}
+
 /// signal_handlerスレッド
 fn spawn_sig_handler(tx: Sender<WorkerMsg>) -> Result<(), DynError> {
-    let mut signals = Signals::new(&[SIGINT, SIGTSTP, SIGCHLD])?;
+    let mut signals = Signals::new([SIGINT, SIGTSTP, SIGCHLD, SIGHUP])?;
     thread::spawn(move || {
         for sig in signals.forever() {
             // シグナルを受信しworkerスレッドに転送
@@ -166,6 +233,109 @@ fn spawn_sig_handler(tx: Sender<WorkerMsg>) -> Result<(), DynError> {
     Ok(())
 }
 
+/// 入力中の行を壊さずに表示するためのプリンタ
+type Printer = Box<dyn ExternalPrinter + Send>;
+
+/// ジョブの状態変化の通知。mainスレッドとworkerスレッドで共有する
+#[derive(Clone, Default)]
+struct Notifier {
+    // 次のプロンプトの前に表示する（プロセスグループID, 通知）
+    queue: Arc<Mutex<Vec<(Pid, String)>>>,
+
+    printer: Arc<Mutex<Option<Printer>>>, // set -bの場合にmainスレッドが作成
+    immediate: Arc<AtomicBool>,           // set -bの場合は真で、すぐに表示する
+}
+
+impl Notifier {
+    /// プロセスグループpgidのジョブの通知を追加。set -bの場合はすぐに表示する。
+    fn push(&self, pgid: Pid, msg: String) {
+        if self.immediate()
+            && let Some(printer) = self.printer.lock().unwrap().as_mut()
+            && printer.print(format!("{}\n", msg)).is_ok()
+        {
+            return;
+        }
+        self.queue.lock().unwrap().push((pgid, msg));
+    }
+
+    /// プロセスグループpgidのジョブの通知がまだ表示されていなければ真。
+    fn pending(&self, pgid: Pid) -> bool {
+        self.queue.lock().unwrap().iter().any(|(g, _)| *g == pgid)
+    }
+
+    /// waitで終了を待ったジョブの、表示されていない通知を取り消す。
+    fn cancel(&self, pgid: Pid) {
+        self.queue.lock().unwrap().retain(|(g, _)| *g != pgid);
+    }
+
+    /// set -bの場合は真。
+    fn immediate(&self) -> bool {
+        self.immediate.load(Ordering::SeqCst)
+    }
+
+    /// set -bの場合のみプリンタを作成し、それ以外では破棄する。
+    /// rustyline 10ではプリンタがあると貼り付けた入力の読み込みが止まることがあるため、常には作成しない。
+    fn update_printer(&self, rl: &mut Editor<()>) {
+        let mut printer = self.printer.lock().unwrap();
+        if !self.immediate() {
+            *printer = None;
+        } else if printer.is_none() {
+            // 端末でなければ作成できず、次のプロンプトの前にまとめて表示する
+            *printer = rl
+                .create_external_printer()
+                .ok()
+                .map(|p| Box::new(p) as Printer);
+        }
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("queue", &self.queue)
+            .field("immediate", &self.immediate)
+            .finish_non_exhaustive()
+    }
+}
+
+/// waitで取得できるように保存する、終了したバックグラウンドのプロセスの数の上限
+const MAX_FINISHED_PIDS: usize = 1024;
+
+/// 終了したプロセスの状態
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ProcExit {
+    Exited(i32),            // 終了コード
+    Signaled(Signal, bool), // 終了させたシグナルと、コアダンプしたかどうか
+}
+
+impl ProcExit {
+    /// 終了コード。シグナルにより終了した場合は128+シグナル番号。
+    fn code(&self) -> i32 {
+        match self {
+            ProcExit::Exited(n) => *n,
+            ProcExit::Signaled(sig, _) => 128 + *sig as i32,
+        }
+    }
+
+    /// jobsや通知で表示するジョブの状態。
+    fn describe(&self) -> String {
+        match self {
+            ProcExit::Exited(0) => "Done".to_string(),
+            ProcExit::Exited(n) => format!("Exit {}", n),
+            ProcExit::Signaled(sig, false) => signal_desc(*sig).to_string(),
+            ProcExit::Signaled(sig, true) => format!("{} (core dumped)", signal_desc(*sig)),
+        }
+    }
+}
+
+/// wait_eventで待った結果
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Waited {
+    Changed,     // 子プロセスの状態変化などを処理した
+    Interrupted, // Ctrl+Cが押された
+    Closed,      // 待つ子プロセスがないか、メッセージを受信できない
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 enum ProcState {
     Run,  // 実行中
@@ -176,6 +346,7 @@ enum ProcState {
 struct ProcInfo {
     state: ProcState, // 実行状態
     pgid: Pid,        // プロセスグループID
+    index: usize,     // パイプライン中の位置
 }
 
 #[derive(Debug)]
@@ -185,63 +356,144 @@ struct Worker {
 
     // ジョブIDから（プロセスグループID, 実行コマンド）へのマップ
     jobs: BTreeMap<usize, (Pid, String)>,
+    job_stack: Vec<usize>, // ジョブID。末尾がカレントジョブ、その1つ前が1つ前のジョブ
 
     // プロセスグループIDから（ジョブID, プロセスID）へのマップ
     pgid_to_pids: HashMap<Pid, (usize, HashSet<Pid>)>,
 
     pid_to_info: HashMap<Pid, ProcInfo>, // プロセスIDからプロセスグループIDへのマップ
-    shell_pgid: Pid,                     // シェルのプロセスグループID
+
+    // プロセスグループIDから、パイプラインの各段の終了コードへのマップ
+    pgid_to_status: HashMap<Pid, Vec<ProcExit>>,
+
+    // 終了したがwaitされていないバックグラウンドのプロセスの（プロセスID, プロセスグループID, 終了コード）。
+    // 終了順で、MAX_FINISHED_PIDSを超えたら古いものから削除
+    finished_pids: VecDeque<(Pid, Pid, i32)>,
+
+    // 終了したがwaitされておらず、通知も表示されていないバックグラウンドのジョブの
+    // （プロセスグループID, 終了コード）。終了順
+    finished_jobs: VecDeque<(Pid, i32)>,
+    shell_pgid: Pid, // シェルのプロセスグループID
+
+    shell_tmodes: Option<Termios>,     // シェル自身の端末の設定
+    job_tmodes: HashMap<Pid, Termios>, // 停止したジョブの、プロセスグループIDから端末の設定へのマップ
+
+    job_control: bool,    // ジョブ制御を行うなら真。サブシェルでは偽
+    exiting: Option<i32>, // exitが実行された場合の終了コード
+    interrupted: bool,    // Ctrl+Cでコマンドラインの実行が中断されたなら真
+    pipefail: bool,       // set -o pipefailなら真
+
+    // 直前のフォアグラウンドのパイプラインの各段の終了コード（PIPESTATUS）
+    pipestatus: Vec<i32>,
+
+    vars: Vars,              // シェル変数
+    last_bg: Option<Pid>,    // 最後にバックグラウンドで実行したプロセスのID（$!）
+    shell_pid: Pid,          // シェルのプロセスID（$$）。サブシェルでも変わらない
+    shell_name: String,      // シェルの名前（$0）
+    positional: Vec<String>, // 位置パラメータ（$1、$2、...）
+    last_arg: String,        // 直前のコマンドの最後の引数（$_）
+
+    // 単純コマンドの展開中に最後に実行したコマンド置換の終了コード
+    subst_status: Option<i32>,
+
+    glob_opts: GlobOptions,    // パス名展開のオプション
+    extglob: Arc<AtomicBool>,  // glob_opts.extglobの写し。mainスレッドが続きの行の判定に使う
+    nohup_pgids: HashSet<Pid>, // disown -hされた、終了時にSIGHUPを送らないジョブ
+
+    cmd_count: usize,           // 実行したコマンドラインの数
+    exit_warned: Option<usize>, // ジョブがあるためexitを警告した時のcmd_count
+    notifier: Notifier,         // バックグラウンドのジョブの状態変化の通知
+
+    // workerスレッドが受信するメッセージ
+    worker_rx: Option<Receiver<WorkerMsg>>,
+}
+
+/// パイプラインの各段
+enum Stage<'a> {
+    Exec(ExecCmd),      // 外部コマンド
+    Builtin(ExecCmd),   // 組み込みコマンド、またはリダイレクトのみのコマンド
+    Shell(&'a Command), // 複合コマンド。サブシェルで実行
+    AndOr(&'a AndOr),   // バックグラウンドで実行する&&、||のリスト。サブシェルで実行
 }
 
 impl Worker {
-    fn new() -> Self {
+    fn new(notifier: Notifier, extglob: Arc<AtomicBool>) -> Self {
         Worker {
             exit_val: 0,
             fg: None, // フォアグラウンドはシェル
             jobs: BTreeMap::new(),
+            job_stack: Vec::new(),
             pgid_to_pids: HashMap::new(),
             pid_to_info: HashMap::new(),
+            pgid_to_status: HashMap::new(),
+            finished_pids: VecDeque::new(),
+            finished_jobs: VecDeque::new(),
 
             // シェルのプロセスグループIDを取得
             // tcgetpgrpを使用することによってshellがフォアグラウンドであるかも検査できる
             shell_pgid: tcgetpgrp(libc::STDIN_FILENO).unwrap(), // libc::STDIN_FILENOは標準入力（0番）
+
+            shell_tmodes: tcgetattr(libc::STDIN_FILENO).ok(),
+            job_tmodes: HashMap::new(),
+
+            job_control: true,
+            exiting: None,
+            interrupted: false,
+            pipefail: false,
+            pipestatus: vec![0],
+            vars: Vars::from_env(),
+            last_bg: None,
+            shell_pid: unistd::getpid(),
+            shell_name: std::env::args()
+                .next()
+                .unwrap_or_else(|| "zerosh".to_string()),
+            positional: Vec::new(),
+            last_arg: String::new(),
+            subst_status: None,
+            glob_opts: GlobOptions::default(),
+            extglob,
+            nohup_pgids: HashSet::new(),
+            cmd_count: 0,
+            exit_warned: None,
+            notifier,
+            worker_rx: None,
         }
     }
 
     /// workerスレッドを起動。
     fn spawn(mut self, worker_rx: Receiver<WorkerMsg>, shell_tx: SyncSender<ShellMsg>) {
         thread::spawn(move || {
-            for msg in worker_rx.iter() {
+            self.worker_rx = Some(worker_rx);
+            while let Some(msg) = self.recv() {
                 // worker_txからメッセージを受信。
                 match msg {
                     WorkerMsg::Cmd(line) => {
-                        match parse_cmd(&line) {
-                            // コマンド実行メッセージの場合、parse_cmdでメッセージをパース。
-                            Ok(cmd) => {
-                                if self.built_in_cmd(&cmd, &shell_tx) {
-                                    // 組み込みコマンド（シェルの内部コマンド）を実行。
-                                    // 組み込みコマンドならworker_rxから受信
-                                    continue;
-                                }
+                        self.cmd_count += 1;
+                        self.interrupted = false;
 
-                                if !self.spawn_child(&line, &cmd) {
-                                    // 組み込みコマンドでない場合は、spawn_childを呼び出し、外部プログラムを実行。
-                                    // 子プロセス生成に失敗した場合、シェルからの入力を再開
-                                    shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap();
-                                }
-                            }
+                        // 終了の通知を表示したジョブは、wait -nの対象から外す
+                        self.finished_jobs
+                            .retain(|(pgid, _)| self.notifier.pending(*pgid));
 
-                            Err(e) => {
-                                eprintln!("ZeroSh: {}", e);
-                                shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap(); // コマンドのパースに失敗した場合は入力を再開するため、main スレッドに通知。
+                        // コマンド実行メッセージの場合、parseで構文木に変換して実行。
+                        match parse(&line, self.glob_opts.extglob) {
+                            Ok(list) => {
+                                self.exec_list(&list);
                             }
+                            Err(e) => eprintln!("ZeroSh: {}", e),
+                        }
+
+                        // リスト全体の実行が終わってから、シェルからの入力を再開、または終了
+                        match self.exiting.take() {
+                            Some(n) => shell_tx.send(ShellMsg::Quit(n)).unwrap(),
+                            None => shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap(),
                         }
                     }
                     WorkerMsg::Signal(SIGCHLD) => {
-                        self.wait_child(&shell_tx); // 子プロセスの状態変化管理。SIGCHLDしぐらぬを受信した場合は、wait_childを呼び出し、子プロセスの状態変化を管理。
+                        self.wait_child(false); // 子プロセスの状態変化管理。SIGCHLDしぐらぬを受信した場合は、wait_childを呼び出し、子プロセスの状態変化を管理。
                     }
                     WorkerMsg::Signal(SIGTSTP) => {
-                        self.wait_child(&shell_tx);
+                        self.wait_child(false);
                     }
                     _ => (), // 無視
                 }
@@ -249,265 +501,2105 @@ impl Worker {
         });
     }
 
-    fn built_in_cmd(&mut self, cmd: &[(&str, Vec<&str>)], shell_tx: &SyncSender<ShellMsg>) -> bool {
-        if cmd.len() > 1 {
-            return false; // 組み込みコマンドのパイプは非対応なのでエラー, 最初のコマンドのみ実行
+    /// workerスレッドが受信するメッセージを1つ受信。
+    /// シェルがSIGHUPを受信した場合は、ジョブにSIGHUPを転送してから終了する。
+    fn recv(&self) -> Option<WorkerMsg> {
+        let msg = self.worker_rx.as_ref()?.recv().ok()?;
+        if let WorkerMsg::Signal(SIGHUP) = msg {
+            self.hangup_jobs();
+            exit(128 + SIGHUP);
         }
+        Some(msg)
+    }
 
-        match cmd[0].0 {
-            "exit" => self.run_exit(&cmd[0].1, shell_tx),
-            "jobs" => self.run_jobs(shell_tx),
-            "fg" => self.run_fg(&cmd[0].1, shell_tx),
-            // "cd" => self.run_cd(&cmd[0].1, shell_tx),
-            _ => false,
-        }
+    /// exitが実行されたか、Ctrl+Cで中断されたなら真。
+    fn aborted(&self) -> bool {
+        self.exiting.is_some() || self.interrupted
     }
 
-    /// 子プロセスを生成。失敗した場合はシェルからの入力を再開させる必要あり。
-    fn spawn_child(&mut self, line: &str, cmd: &[(&str, Vec<&str>)]) -> bool {
-        assert_ne!(cmd.len(), 0); // コマンドが空でないか検査
+    /// リストを実行し、最後に実行したコマンドの終了コードを返す。
+    fn exec_list(&mut self, list: &List) -> i32 {
+        for item in list.items.iter() {
+            if self.aborted() {
+                break; // exitが実行された場合や、Ctrl+Cで中断された場合は残りを実行しない
+            }
 
-        // ジョブIDを取得
-        let job_id = if let Some(id) = self.get_new_job_id() {
-            id
+            if item.bg {
+                self.exec_bg(&item.and_or);
+            } else {
+                self.exec_and_or(&item.and_or);
+            }
+        }
+        self.exit_val
+    }
+
+    /// バックグラウンドで実行。ジョブの終了は待たない。
+    fn exec_bg(&mut self, and_or: &AndOr) {
+        // 単独のパイプラインはそのまま実行し、それ以外はサブシェルで実行
+        let stages = if and_or.rest.is_empty() && !and_or.first.negated {
+            self.make_stages(&and_or.first)
         } else {
-            eprintln!("ZeroSh: 管理可能なジョブを最大値に到達。");
-            return false;
+            Some(vec![Stage::AndOr(and_or)])
         };
 
-        if cmd.len() > 2 {
-            eprintln!("ZeroSh: 3つ以上のコマンドによるパイプはサポートしていません");
-            return false;
-        }
+        self.exit_val = match stages {
+            Some(stages) if self.spawn_child(&and_or.to_string(), &stages, false) => 0,
+            _ => 1,
+        };
+    }
 
-        let mut input = None; // 2つ目のプロセスの標準入力
-        let mut output = None; // 1つ目のプロセスの標準出力
-        if cmd.len() == 2 {
-            // パイプを作成
-            let p = pipe().unwrap();
-            input = Some(p.0); // 読み取り専用のファイルディスクリプタ(3番)
-            output = Some(p.1); // 書き込み専用のファイルディスクリプタ（4番）
+    /// &&、||で連結されたパイプラインを実行。直前の終了コードによって、次を実行するか決める。
+    fn exec_and_or(&mut self, and_or: &AndOr) -> i32 {
+        let mut status = self.exec_pipeline(&and_or.first);
+        for (op, pipeline) in and_or.rest.iter() {
+            if self.aborted() {
+                break;
+            }
+
+            let run = match op {
+                AndOrOp::And => status == 0,
+                AndOrOp::Or => status != 0,
+            };
+            if run {
+                status = self.exec_pipeline(pipeline);
+            }
         }
+        status
+    }
 
-        // パイプを閉じる関数を定義
-        let cleanup_pipe = CleanUp {
-            f: || {
-                if let Some(fd) = input {
-                    syscall(|| unistd::close(fd)).unwrap();
+    /// パイプラインを実行し、終了コードを返す。
+    fn exec_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        let status = if let [cmd] = pipeline.cmds.as_slice() {
+            let status = self.exec_command(cmd);
+            self.pipestatus = vec![status];
+            status
+        } else {
+            match self.make_stages(pipeline) {
+                Some(stages) if self.spawn_child(&pipeline.to_string(), &stages, true) => {
+                    self.wait_fg(); // 各段の終了コードはmanage_jobでpipestatusに保存される
+                    self.exit_val
                 }
-
-                if let Some(fd) = output {
-                    syscall(|| unistd::close(fd)).unwrap();
+                _ => {
+                    self.pipestatus = vec![1];
+                    1
                 }
-            },
-        };
-
-        let pgid;
-        // 1つ目のプロセスを生成
-        match fork_exec(Pid::from_raw(0), cmd[0].0, &cmd[0].1, None, output) {
-            // from_rawの引数に0を入れると自動でpgidを割り当てる
-            Ok(child) => pgid = child,
-            Err(e) => {
-                eprintln!("ZeroSh: プロセス生成エラー: {}", e);
-                return false;
             }
-        }
+        };
 
-        // プロセス、ジョブの情報を追加
-        let info = ProcInfo {
-            state: ProcState::Run,
-            pgid,
+        let status = if pipeline.negated {
+            (status == 0) as i32
+        } else {
+            status
         };
-        let mut pids = HashMap::new();
-        pids.insert(pgid, info.clone()); // 1つ目のプロセスの取得
+        self.exit_val = status;
+        status
+    }
 
-        // 2つ目のプロセスのを生成
-        if cmd.len() == 2 {
-            match fork_exec(pgid, cmd[1].0, &cmd[1].1, input, None) {
-                Ok(child) => {
-                    pids.insert(child, info);
-                }
+    /// パイプラインの各段を変換。展開に失敗した場合はエラーを表示してNoneを返す。
+    fn make_stages<'a>(&mut self, pipeline: &'a Pipeline) -> Option<Vec<Stage<'a>>> {
+        let mut stages = Vec::new();
+        for cmd in pipeline.cmds.iter() {
+            match self.make_stage(cmd) {
+                Ok(stage) => stages.push(stage),
                 Err(e) => {
-                    eprintln!("ZeroSh: プロセス生成エラー: {}", e);
-                    return false;
+                    eprintln!("ZeroSh: {}", e);
+                    return None;
                 }
             }
         }
-
-        std::mem::drop(cleanup_pipe); // パイプをクローズ
-
-        // ジョブ情報を追加して子プロセスをフォアグラウンドプロセスグループにする
-        self.fg = Some(pgid);
-        self.insert_job(job_id, pgid, pids, line);
-        tcsetpgrp(libc::STDIN_FILENO, pgid).unwrap();
-
-        true
+        if self.interrupted {
+            return None; // コマンド置換の実行中にCtrl+Cで中断された
+        }
+        Some(stages)
     }
 
-    /// exitコマンドを実行
-    fn run_exit(&mut self, args: &[&str], shell_tx: &SyncSender<ShellMsg>) -> bool {
-        // 実行中のジョブがある場合は終了しない
-        if !self.jobs.is_empty() {
-            eprintln!("ジョブが実行中なので終了できません。");
-            self.exit_val = 1; // 失敗
-            shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap(); // シェルを再開
-            return true;
+    /// パイプラインの1段として実行するコマンドを変換。
+    fn make_stage<'a>(&mut self, cmd: &'a Command) -> Result<Stage<'a>, DynError> {
+        match cmd {
+            Command::Simple(simple) => {
+                let exec = self.expand_simple(simple)?;
+                if exec.args.first().is_some_and(|name| !is_builtin(name)) {
+                    Ok(Stage::Exec(exec))
+                } else {
+                    Ok(Stage::Builtin(exec))
+                }
+            }
+            Command::Compound(..) => Ok(Stage::Shell(cmd)),
         }
+    }
 
-        // 終了コードを取得
-        let exit_val = if let Some(s) = args.get(1) {
-            if let Ok(n) = (*s).parse::<i32>() {
-                n
-            } else {
-                // 終了コードが整数ではない（i32のparseに失敗)
-                eprintln!("{}は不正な引数です。", s);
-                self.exit_val = 1;
-                shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap(); // シェルを再開
-                return true;
+    /// パイプラインでない単独のコマンドを実行し、終了コードを返す。
+    /// 組み込みコマンドと、サブシェル以外の複合コマンドはシェル自身で実行する。
+    fn exec_command(&mut self, cmd: &Command) -> i32 {
+        let stage = match cmd {
+            Command::Compound(CompoundCommand::Subshell(_), _) => Stage::Shell(cmd),
+            Command::Compound(compound, redirects) => {
+                return match self.expand_redirects(redirects) {
+                    Ok(redirs) => self.with_redirs(&redirs, |w| w.exec_compound(compound)),
+                    Err(e) => {
+                        eprintln!("ZeroSh: {}", e);
+                        1
+                    }
+                };
             }
-        } else {
+            Command::Simple(_) => match self.make_stage(cmd) {
+                // コマンド置換の実行中にCtrl+Cで中断された場合は実行しない
+                _ if self.interrupted => return 128 + SIGINT,
+                Ok(Stage::Builtin(exec)) => {
+                    return self.with_redirs(&exec.redirs, |w| w.exec_builtin(&exec));
+                }
+                Ok(stage) => stage,
+                Err(e) => {
+                    eprintln!("ZeroSh: {}", e);
+                    return 1;
+                }
+            },
+        };
+
+        if self.spawn_child(&cmd.to_string(), &[stage], true) {
+            self.wait_fg();
             self.exit_val
+        } else {
+            1
+        }
+    }
+
+    /// 複合コマンドを実行し、終了コードを返す。
+    /// サブシェルの場合、既にforkした子プロセスで呼び出されていること。
+    fn exec_compound(&mut self, cmd: &CompoundCommand) -> i32 {
+        let status = match cmd {
+            CompoundCommand::Subshell(list) | CompoundCommand::Group(list) => self.exec_list(list),
+            CompoundCommand::If { conds, else_body } => 'if_cmd: {
+                for (cond, body) in conds.iter() {
+                    if self.exec_list(cond) == 0 {
+                        break 'if_cmd self.exec_list(body);
+                    }
+                    if self.aborted() {
+                        break 'if_cmd self.exit_val;
+                    }
+                }
+                match else_body {
+                    Some(body) => self.exec_list(body),
+                    None => 0, // どの条件にも合わなければ0
+                }
+            }
+            CompoundCommand::While { cond, body, until } => {
+                let mut status = 0;
+                while !self.aborted() {
+                    let c = self.exec_list(cond);
+                    if (c == 0) == *until || self.aborted() {
+                        break;
+                    }
+                    status = self.exec_list(body);
+                }
+                status
+            }
+            CompoundCommand::For { var, words, body } => 'for_cmd: {
+                let mut items = Vec::new();
+                for word in words.iter().flatten() {
+                    match self.expand_word(word) {
+                        Ok(fields) => items.extend(fields),
+                        Err(e) => {
+                            eprintln!("ZeroSh: {}", e);
+                            break 'for_cmd 1;
+                        }
+                    }
+                }
+                let mut status = 0;
+                for item in items {
+                    if self.aborted() {
+                        break;
+                    }
+                    if let Err(e) = self.vars.set(var, item) {
+                        eprintln!("ZeroSh: {}", e);
+                        break 'for_cmd 1;
+                    }
+                    status = self.exec_list(body);
+                }
+                status
+            }
+            CompoundCommand::Case { word, arms } => match self.match_case(word, arms) {
+                Ok(Some(arm)) => self.exec_list(&arm.body),
+                Ok(None) => 0,
+                Err(e) => {
+                    eprintln!("ZeroSh: {}", e);
+                    1
+                }
+            },
+            CompoundCommand::Arith(expr) => match self.eval_arith(expr) {
+                Ok(n) => (n == 0) as i32,
+                Err(e) => {
+                    eprintln!("ZeroSh: {}", e);
+                    1
+                }
+            },
+            CompoundCommand::Cond(expr) => match self.eval_cond(expr) {
+                Ok(result) => !result as i32,
+                Err(e) => {
+                    eprintln!("ZeroSh: {}", e);
+                    2
+                }
+            },
         };
+        self.exit_val = status;
+        status
+    }
 
-        shell_tx.send(ShellMsg::Quit(exit_val)).unwrap();
-        true
+    /// [[ ]]の条件式を評価。&&と||は短絡評価する。
+    fn eval_cond(&mut self, expr: &CondExpr) -> Result<bool, DynError> {
+        let result = match expr {
+            CondExpr::Word(word) => !self.expand_word_str(word)?.is_empty(),
+            CondExpr::Unary(op, word) => cond::unary(op, &self.expand_word_str(word)?),
+            CondExpr::Binary(lhs, op, rhs) => {
+                let lhs = self.expand_word_str(lhs)?;
+                match op.as_str() {
+                    // 右辺はパターンとして扱う。クォートされた部分はリテラル
+                    "==" | "=" | "!=" => {
+                        let hit = Pattern::new(&self.expand_pattern(rhs)?, self.glob_opts.extglob)
+                            .matches(&lhs);
+                        hit == (op != "!=")
+                    }
+                    _ => cond::binary(&lhs, op, &self.expand_word_str(rhs)?)?,
+                }
+            }
+            CondExpr::Not(e) => !self.eval_cond(e)?,
+            CondExpr::And(l, r) => self.eval_cond(l)? && self.eval_cond(r)?,
+            CondExpr::Or(l, r) => self.eval_cond(l)? || self.eval_cond(r)?,
+        };
+        Ok(result)
     }
 
-    /// jobsコマンドを実行
-    fn run_jobs(&self, shell_tx: &SyncSender<ShellMsg>) -> bool {
-        let jobs = &self.jobs;
-        for (job_id, (pgid, (pid, line))) in jobs.iter().enumerate() {
-            println!("[{}]: pgid: {} pid: {}  {}", job_id, pgid, pid, line);
+    /// caseコマンドで、単語に最初にマッチするパターンを持つ節を返す。
+    fn match_case<'a>(
+        &mut self,
+        word: &Word,
+        arms: &'a [CaseArm],
+    ) -> Result<Option<&'a CaseArm>, DynError> {
+        let s = self.expand_word_str(word)?;
+        for arm in arms.iter() {
+            for p in arm.patterns.iter() {
+                if Pattern::new(&self.expand_pattern(p)?, self.glob_opts.extglob).matches(&s) {
+                    return Ok(Some(arm));
+                }
+            }
         }
-
-        shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap();
-        true
+        Ok(None)
     }
 
-    /// fgコマンドを実行。
-    fn run_fg(&mut self, args: &[&str], shell_tx: &SyncSender<ShellMsg>) -> bool {
-        self.exit_val = 1; // とりあえず失敗に設定
+    /// サブシェルとして実行するパイプラインの1段を、forkした子プロセスで実行。
+    fn exec_stage(&mut self, stage: &Stage) -> i32 {
+        match stage {
+            Stage::Exec(_) => unreachable!(), // 外部コマンドはfork_execで実行
+            Stage::Builtin(exec) => {
+                for redir in exec.redirs.iter() {
+                    if let Err(e) = redir.apply() {
+                        eprintln!("ZeroSh: {}", e);
+                        return 1;
+                    }
+                }
+                self.exec_builtin(exec)
+            }
+            Stage::Shell(Command::Compound(compound, redirects)) => {
+                let redirs = match self.expand_redirects(redirects) {
+                    Ok(redirs) => redirs,
+                    Err(e) => {
+                        eprintln!("ZeroSh: {}", e);
+                        return 1;
+                    }
+                };
+                for redir in redirs.iter() {
+                    if let Err(e) = redir.apply() {
+                        eprintln!("ZeroSh: {}", e);
+                        return 1;
+                    }
+                }
+                self.exec_compound(compound)
+            }
+            Stage::Shell(Command::Simple(_)) => unreachable!(),
+            Stage::AndOr(and_or) => self.exec_and_or(and_or),
+        }
+    }
 
-        // 引数をチェック
-        if args.len() < 2 {
-            eprintln!("usage: fg <num>");
-            shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap(); // シェルを再開
-            return false;
+    /// 単純コマンドの単語とリダイレクトを展開し、実行できる形に変換。
+    fn expand_simple(&mut self, cmd: &SimpleCommand) -> Result<ExecCmd, DynError> {
+        self.subst_status = None;
+        let mut args: Vec<String> = Vec::new();
+        for word in cmd.words.iter() {
+            // export A=$Bのような宣言コマンドの代入は、フィールド分割しない
+            if matches!(
+                args.first().map(|s| s.as_str()),
+                Some("export" | "readonly")
+            ) && let Some(WordPart::Lit(first)) = word.parts.first()
+                && first.split_once('=').is_some_and(|(name, _)| is_name(name))
+            {
+                args.push(self.expand_word_str(word)?);
+            } else {
+                args.extend(self.expand_word(word)?);
+            }
         }
 
-        // ジョブIDを取得
-        if let Ok(n) = args[1].parse::<usize>() {
-            if let Some((pgid, cmd)) = self.jobs.get(&n) {
-                eprintln!("[{}] 再開\t{}", n, cmd);
+        let mut assigns = Vec::new();
+        for a in cmd.assigns.iter() {
+            assigns.push((a.name.clone(), self.expand_word_str(&a.value)?));
+        }
+        let redirs = self.expand_redirects(&cmd.redirects)?;
+        if let Some(last) = args.last() {
+            self.last_arg = last.clone();
+        }
+        Ok(ExecCmd {
+            args,
+            assigns,
+            redirs,
+        })
+    }
 
-                // フォアグラウンドプロセスに設定
-                self.fg = Some(*pgid);
-                tcsetpgrp(libc::STDIN_FILENO, *pgid).unwrap();
+    /// リダイレクトを展開し、適用できる形に変換。
+    fn expand_redirects(&mut self, redirects: &[Redirect]) -> Result<Vec<Redir>, DynError> {
+        let mut redirs = Vec::new();
+        for r in redirects {
+            let (target, body) = match &r.heredoc {
+                // ヒアドキュメントの区切り文字列は展開しない
+                Some(body) => (r.target.unquote(), self.expand_word_str(body)?),
+                None => match self.expand_word(&r.target)?.as_slice() {
+                    [target] => (target.clone(), String::new()),
+                    _ => return Err(format!("{}: ambiguous redirect", r.target).into()),
+                },
+            };
+            redirs.extend(Redir::from_ast(r, target, body)?);
+        }
+        Ok(redirs)
+    }
 
-                // ジョブの実行を再開
-                killpg(*pgid, Signal::SIGCONT).unwrap();
-                return true;
+    /// 単語をブレース展開した後に展開し、フィールド分割とパス名展開をした結果を返す。
+    fn expand_word(&mut self, word: &Word) -> Result<Vec<String>, DynError> {
+        let mut result = Vec::new();
+        for word in brace::expand(word) {
+            let mut fields = Vec::new();
+            let mut cur = None;
+            self.expand_parts(&word.parts, false, true, &mut fields, &mut cur)?;
+            fields.extend(cur);
+
+            for field in fields {
+                result.extend(glob::expand(&field, &self.glob_opts)?);
             }
-        };
-
-        // 失敗
-        eprintln!("{}というジョブは見つかりませんでした。", args[1]);
-        shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap(); // シェルを再開
-        true
+        }
+        Ok(result)
     }
 
-    /// 子プロセスの状態変化を管理。
-    fn wait_child(&mut self, shell_tx: &SyncSender<ShellMsg>) {
-        // WUNTRACED: 子プロセスの停止
-        // WNOHANG: ブロックしない
-        // WCONTINUED: 実行再開
-        let flag = Some(WaitPidFlag::WUNTRACED | WaitPidFlag::WNOHANG | WaitPidFlag::WCONTINUED);
+    /// 単語を展開し、フィールド分割せずに1つの文字列として返す。
+    /// 代入する値やcaseの単語など。
+    fn expand_word_str(&mut self, word: &Word) -> Result<String, DynError> {
+        let mut fields = Vec::new();
+        let mut cur = None;
+        self.expand_parts(&word.parts, true, false, &mut fields, &mut cur)?;
+        fields.extend(cur);
+        Ok(fields.join(" "))
+    }
 
-        loop {
-            match syscall(|| waitpid(Pid::from_raw(-1), flag)) {
-                // from_rawの引き数に-1を指定することで任意の子プロセスの状態変化を検知
-                Ok(WaitStatus::Exited(pid, status)) => {
-                    // プロセスが終了
-                    self.exit_val = status; // 終了コードを保存
-                    self.process_term(pid, shell_tx);
-                }
-                Ok(WaitStatus::Signaled(pid, sig, core)) => {
-                    // プロセスがシグナルにより終了
-                    eprintln!(
-                        "\nZeroSh: 子プロセスがシグナルにより終了{}: pid = {}, signal = {}",
-                        if core { "（コアダンプ）" } else { "" },
-                        pid,
-                        sig
-                    );
-                }
-                // プロセスが停止
-                Ok(WaitStatus::Stopped(pid, _sig)) => {
-                    println!("stopped!");
-                    self.process_stop(pid, shell_tx)
+    /// 単語の要素を展開してfieldsに追加。curは作成中のフィールドで、
+    /// クォートされた空文字列のように、空でもフィールドとなる場合はSome("")となる。
+    /// quotedが真ならダブルクォートの中で、展開結果をフィールド分割しない。
+    /// globが真ならパス名展開のため、クォートされた文字をpattern::escapeでエスケープする。
+    fn expand_parts(
+        &mut self,
+        parts: &[WordPart],
+        quoted: bool,
+        glob: bool,
+        fields: &mut Vec<String>,
+        cur: &mut Option<String>,
+    ) -> Result<(), DynError> {
+        let lit = |s: &str| {
+            if glob {
+                pattern::escape(s)
+            } else {
+                s.to_string()
+            }
+        };
+        for part in parts {
+            match part {
+                WordPart::Lit(s) if !quoted => cur.get_or_insert_default().push_str(s),
+                WordPart::Lit(s) | WordPart::Quoted(s) => {
+                    cur.get_or_insert_default().push_str(&lit(s))
                 }
-                // プロセスが実行再開
-                Ok(WaitStatus::Continued(pid)) => self.process_continue(pid),
-                Ok(WaitStatus::StillAlive) => return, // waitすべき子プロセスはいない。
-                Err(nix::Error::ECHILD) => return,    // 子プロセスはいない
-                Err(e) => {
-                    eprintln!("\nZeroSh: waitが失敗: {}", e);
-                    exit(1);
+                WordPart::DQuoted(inner) => {
+                    // "${A[@]}"は要素がなければフィールドにならない
+                    if !inner.iter().any(is_at_param) {
+                        cur.get_or_insert_default();
+                    }
+                    self.expand_parts(inner, true, glob, fields, cur)?;
                 }
-                #[cfg(any(target_os = "linux", target_os = "android"))]
-                Ok(WaitStatus::PtraceEvent(pid, _, _) | WaitStatus::PtrasySyscall(pid)) => {
-                    self.process_stop(pid, shell_tx)
+                WordPart::Param(_) | WordPart::CmdSubst(_) | WordPart::Arith(_) => {
+                    let values = self.part_values(part)?;
+                    if !quoted {
+                        // クォートされていない展開結果は、IFSの文字で区切って別のフィールドとする
+                        let ifs = self.vars.get("IFS").unwrap_or(" \t\n").to_string();
+                        for (i, value) in values.iter().enumerate() {
+                            if i > 0 {
+                                fields.extend(cur.take());
+                            }
+                            for c in value.chars() {
+                                if ifs.contains(c) {
+                                    fields.extend(cur.take());
+                                } else if glob && c == '\\' {
+                                    // *などはパターンとして扱うが、\はエスケープとみなさない
+                                    cur.get_or_insert_default().push_str("\\\\");
+                                } else {
+                                    cur.get_or_insert_default().push(c);
+                                }
+                            }
+                        }
+                    } else if is_at_param(part) {
+                        // "${A[@]}"は要素ごとに別のフィールドとする
+                        for (i, value) in values.into_iter().enumerate() {
+                            if i > 0 {
+                                fields.extend(cur.take());
+                            }
+                            cur.get_or_insert_default().push_str(&lit(&value));
+                        }
+                    } else {
+                        // "${A[*]}"はIFSの先頭の文字で連結する
+                        let sep = match self.vars.get("IFS") {
+                            Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                            None => " ".to_string(),
+                        };
+                        cur.get_or_insert_default()
+                            .push_str(&lit(&values.join(&sep)));
+                    }
                 }
             }
         }
+        Ok(())
     }
 
-    /// プロセスの終了処理。
-    fn process_term(&mut self, pid: Pid, shell_tx: &SyncSender<ShellMsg>) {
-        // プロセスのIDを削除し、必要ならフォアグラウンドプロセスにシェルを設定
-        if let Some((job_id, pgid)) = self.remove_pid(pid) {
-            self.manage_job(job_id, pgid, shell_tx);
+    /// パターンとして単語を展開。クォートされた部分はエスケープしてリテラルとする。
+    fn expand_pattern(&mut self, word: &Word) -> Result<String, DynError> {
+        let mut s = String::new();
+        self.pattern_parts(&word.parts, false, &mut s)?;
+        Ok(s)
+    }
+
+    /// パターンとして単語の要素を展開してsに追加。
+    fn pattern_parts(
+        &mut self,
+        parts: &[WordPart],
+        quoted: bool,
+        s: &mut String,
+    ) -> Result<(), DynError> {
+        for part in parts {
+            match part {
+                WordPart::Lit(t) if !quoted => s.push_str(t),
+                WordPart::Lit(t) | WordPart::Quoted(t) => s.push_str(&pattern::escape(t)),
+                WordPart::DQuoted(inner) => self.pattern_parts(inner, true, s)?,
+                WordPart::Param(_) | WordPart::CmdSubst(_) | WordPart::Arith(_) => {
+                    // クォートされていない展開結果は、パターンとして解釈する
+                    let value = self.part_values(part)?.join(" ");
+                    if quoted {
+                        s.push_str(&pattern::escape(&value));
+                    } else {
+                        s.push_str(&value);
+                    }
+                }
+            }
         }
+        Ok(())
     }
 
-    /// プロセスの停止処理。
-    fn process_stop(&mut self, pid: Pid, shell_tx: &SyncSender<ShellMsg>) {
-        self.set_pid_state(pid, ProcState::Stop); // プロセスを停止中に設定
-        let pgid = self.pid_to_info.get(&pid).unwrap().pgid; // プロセスグループIDを取得
-        let job_id = self.pgid_to_pids.get(&pgid).unwrap().0; // ジョブIDを取得
-        self.manage_job(job_id, pgid, shell_tx); // 必要ならフォアグラウンドプロセスをシェルに設定
+    /// パラメータ展開、コマンド置換、または算術式展開の結果。
+    fn part_values(&mut self, part: &WordPart) -> Result<Vec<String>, DynError> {
+        match part {
+            WordPart::Param(p) => self.param_values(p),
+            WordPart::CmdSubst(src) => Ok(vec![self.command_subst(src)?]),
+            WordPart::Arith(expr) => Ok(vec![self.eval_arith(expr)?.to_string()]),
+            _ => unreachable!(),
+        }
     }
 
-    /// プロセスの再開処理
-    fn process_continue(&mut self, pid: Pid) {
-        self.set_pid_state(pid, ProcState::Run);
+    /// 算術式を展開してから評価。
+    fn eval_arith(&mut self, expr: &Word) -> Result<i64, DynError> {
+        let s = self.expand_word_str(expr)?;
+        Ok(arith::eval(&s, &mut self.vars)?)
     }
 
-    ///　ジョブの管理。引数には変化のあったジョブとプロセスグループを指定。
-    ///
-    /// - フォアグラウンドプロセスが空の場合、シェルをフォアグラウンドに設定。
-    /// - フォアグラウンドプロセスが全て停止中の場合、シェルをフォアグラウンドに設定。
-    fn manage_job(&mut self, job_id: usize, pgid: Pid, shell_tx: &SyncSender<ShellMsg>) {
-        let is_fg = self.fg.map_or(false, |x| pgid == x); // フォアグラウンドのプロセスか?
-        let line = &self.jobs.get(&job_id).unwrap().1;
-        if is_fg {
-            // 状態が変化したプロセスはフォアグラウンドに設定
-            if self.is_group_empty(pgid).unwrap() {
-                // フォアグラウンドプロセスが空の場合、
-                // ジョブ情報を削除してシェルをフォアグラウンドに設定
-                eprintln!("[{}] 終了\t{}", job_id, line);
+    /// コマンド置換を実行。サブシェルでsrcを実行し、標準出力から末尾の改行を除いたものを返す。
+    fn command_subst(&mut self, src: &str) -> Result<String, DynError> {
+        let list = parse(src, self.glob_opts.extglob)?;
+        let (reader, writer) = pipe2(OFlag::O_CLOEXEC)?;
+        let pipes = [(reader, writer)];
+        let child = self.fork_subshell(None, None, Some(writer), &pipes, |w| w.exec_list(&list));
+        syscall(|| unistd::close(writer))?;
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                syscall(|| unistd::close(reader))?;
+                return Err(e);
+            }
+        };
+
+        let mut out = Vec::new();
+        let result = unsafe { File::from_raw_fd(reader) }.read_to_end(&mut out);
+        let exit = loop {
+            match syscall(|| waitpid(child, None))? {
+                WaitStatus::Exited(_, status) => break ProcExit::Exited(status),
+                WaitStatus::Signaled(_, sig, core) => break ProcExit::Signaled(sig, core),
+                _ => (),
+            }
+        };
+        result?;
+
+        if exit == ProcExit::Signaled(Signal::SIGINT, false) && !self.interrupted {
+            // Ctrl+Cで中断された場合は、残りのコマンドも実行しない
+            self.interrupted = true;
+            eprintln!();
+        }
+        self.subst_status = Some(exit.code());
+        self.exit_val = exit.code();
+
+        let mut s = String::from_utf8_lossy(&out).into_owned();
+        s.truncate(s.trim_end_matches('\n').len());
+        Ok(s)
+    }
+
+    /// パラメータ展開の結果。配列の場合は添字に応じた要素のそれぞれに演算を適用する。
+    fn param_values(&mut self, p: &Param) -> Result<Vec<String>, DynError> {
+        let values = self.lookup_param(p);
+        let Some(op) = &p.op else {
+            return Ok(values.unwrap_or_default());
+        };
+        let values = match op {
+            ParamOp::Length => {
+                let values = values.unwrap_or_default();
+                let n = if is_all_elements(p) {
+                    values.len() // 配列の要素数
+                } else {
+                    values.first().map_or(0, |v| v.chars().count())
+                };
+                vec![n.to_string()]
+            }
+            ParamOp::Test { op, colon, word } => {
+                // :がある場合は、空文字列も設定されていないとみなす
+                let set = values
+                    .as_ref()
+                    .is_some_and(|v| !colon || v.iter().any(|s| !s.is_empty()));
+                match (op, set) {
+                    ('-', true) | ('=', true) | ('?', true) => values.unwrap_or_default(),
+                    ('-', false) => vec![self.expand_word_str(word)?],
+                    ('=', false) => {
+                        let value = self.expand_word_str(word)?;
+                        self.vars.set(&p.name, value.clone())?;
+                        vec![value]
+                    }
+                    ('?', false) => {
+                        let mut msg = self.expand_word_str(word)?;
+                        if msg.is_empty() {
+                            msg = "parameter null or not set".to_string();
+                        }
+                        return Err(format!("{}: {}", p.name, msg).into());
+                    }
+                    ('+', true) => vec![self.expand_word_str(word)?],
+                    _ => Vec::new(),
+                }
+            }
+            ParamOp::Trim {
+                suffix,
+                longest,
+                pattern,
+            } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?, self.glob_opts.extglob);
+                let values = values.unwrap_or_default().into_iter();
+                values
+                    .map(|v| pattern.strip(&v, *suffix, *longest))
+                    .collect()
+            }
+            ParamOp::Replace { all, pattern, rep } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?, self.glob_opts.extglob);
+                let rep = self.expand_word_str(rep)?;
+                let values = values.unwrap_or_default().into_iter();
+                values.map(|v| pattern.replace(&v, &rep, *all)).collect()
+            }
+            ParamOp::Substr { offset, length } => {
+                // 開始位置と長さは算術式
+                let offset = self.eval_arith(offset)?;
+                let length = match length {
+                    Some(length) => Some(self.eval_arith(length)?),
+                    None => None,
+                };
+                let mut values = values.unwrap_or_default();
+                if matches!(p.name.as_str(), "@" | "*") {
+                    values.insert(0, self.shell_name.clone()); // ${@:0}は$0から始まる
+                }
+                if is_all_elements(p) {
+                    // 配列の場合は要素を切り出す
+                    substr(&values, offset, length)?
+                } else {
+                    values
+                        .iter()
+                        .map(|v| {
+                            let chars: Vec<char> = v.chars().collect();
+                            let sub = substr(&chars, offset, length)?;
+                            Ok(sub.into_iter().collect())
+                        })
+                        .collect::<Result<_, DynError>>()?
+                }
+            }
+            ParamOp::Case { upper, all } => {
+                let values = values.unwrap_or_default().into_iter();
+                values.map(|v| convert_case(&v, *upper, *all)).collect()
+            }
+        };
+        Ok(values)
+    }
+
+    /// パラメータの値。配列の場合は添字に応じた要素となる。設定されていない場合はNone。
+    fn lookup_param(&self, p: &Param) -> Option<Vec<String>> {
+        let values: Vec<String> = match p.name.as_str() {
+            "@" | "*" => return Some(self.positional.clone()),
+            "?" => vec![self.exit_val.to_string()],
+            "$" => vec![self.shell_pid.to_string()],
+            "!" => vec![self.last_bg?.to_string()],
+            "#" => vec![self.positional.len().to_string()],
+            "-" => vec![self.option_flags()],
+            "_" => vec![self.last_arg.clone()],
+            "0" => vec![self.shell_name.clone()],
+            "PIPESTATUS" => self.pipestatus.iter().map(|n| n.to_string()).collect(),
+            name => match name.parse::<usize>() {
+                Ok(0) => vec![self.shell_name.clone()], // ${00}は$0と同じ
+                Ok(n) => vec![self.positional.get(n - 1)?.clone()],
+                Err(_) => vec![self.vars.get(name)?.to_string()],
+            },
+        };
+
+        match p.index.as_deref() {
+            None => values.into_iter().next().map(|v| vec![v]), // 配列の場合は先頭の要素
+            Some("@" | "*") => Some(values),
+            Some(index) => {
+                let i = index.trim().parse::<usize>().ok()?;
+                values.into_iter().nth(i).map(|v| vec![v])
+            }
+        }
+    }
+
+    /// シェル自身にリダイレクトを適用してfを実行し、終了後に元に戻す。
+    fn with_redirs<F>(&mut self, redirs: &[Redir], f: F) -> i32
+    where
+        F: FnOnce(&mut Worker) -> i32,
+    {
+        // (リダイレクト先のファイルディスクリプタ, 退避先)。閉じていた場合の退避先はNone
+        let mut saved: Vec<(RawFd, Option<RawFd>)> = Vec::new();
+        let mut status = None;
+        for redir in redirs.iter() {
+            let fd = redir.fd();
+            if !saved.iter().any(|(s, _)| *s == fd) {
+                let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok();
+                saved.push((fd, copy));
+            }
+            if let Err(e) = redir.apply() {
+                eprintln!("ZeroSh: {}", e);
+                status = Some(1);
+                break;
+            }
+        }
+
+        let status = status.unwrap_or_else(|| f(self));
+
+        // 元に戻す前に、バッファに残っている出力をリダイレクト先に書き出す
+        let _ = std::io::stdout().flush();
+        for (fd, copy) in saved.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    let _ = syscall(|| dup2(copy, fd));
+                    let _ = syscall(|| unistd::close(copy));
+                }
+                None => {
+                    let _ = syscall(|| unistd::close(fd));
+                }
+            }
+        }
+        status
+    }
+
+    /// 組み込みコマンド、またはコマンド名のない単純コマンドを実行し、終了コードを返す。
+    ///
+    /// コマンド名がなければ代入はシェルの変数に行い、
+    /// あれば組み込みコマンドの実行中のみ一時的に行う。
+    /// ただし、exportなどPOSIXの特殊組み込みコマンドの場合は代入を残す。
+    fn exec_builtin(&mut self, exec: &ExecCmd) -> i32 {
+        let special = exec.args.first().is_some_and(|name| {
+            matches!(
+                name.as_str(),
+                "exit" | "export" | "readonly" | "set" | "unset"
+            )
+        });
+        if special || exec.args.is_empty() {
+            for (name, value) in exec.assigns.iter() {
+                if let Err(e) = self.vars.set(name, value.clone()) {
+                    eprintln!("ZeroSh: {}", e);
+                    return 1;
+                }
+            }
+            if exec.args.is_empty() {
+                // 代入のみのコマンドは、コマンド置換があればその終了コードを返す
+                return self.subst_status.unwrap_or(0);
+            }
+            return self.built_in_cmd(&exec.args);
+        }
+
+        let mut saved = Vec::new(); // 代入前の変数
+        let mut status = None;
+        for (name, value) in exec.assigns.iter() {
+            saved.push((name, self.vars.get_var(name).cloned()));
+            if let Err(e) = self.vars.set(name, value.clone()) {
+                eprintln!("ZeroSh: {}", e);
+                status = Some(1);
+                break;
+            }
+        }
+
+        let status = status.unwrap_or_else(|| self.built_in_cmd(&exec.args));
+        for (name, var) in saved.into_iter().rev() {
+            self.vars.restore(name, var);
+        }
+        status
+    }
+
+    /// 組み込みコマンドを実行し、終了コードを返す。
+    fn built_in_cmd(&mut self, args: &[String]) -> i32 {
+        match args[0].as_str() {
+            "exit" => self.run_exit(args),
+            "jobs" => self.run_jobs(args),
+            "fg" => self.run_fg(args),
+            "bg" => self.run_bg(args),
+            "kill" => self.run_kill(args),
+            "wait" => self.run_wait(args),
+            "disown" => self.run_disown(args),
+            "set" => self.run_set(args),
+            "export" => self.run_export(args),
+            "readonly" => self.run_readonly(args),
+            "unset" => self.run_unset(args),
+            "test" | "[" => self.run_test(args),
+            "cd" => self.run_cd(args),
+            "let" => self.run_let(args),
+            "shopt" => self.run_shopt(args),
+            _ => unreachable!(),
+        }
+    }
+
+    /// 子プロセスを生成。生成に成功した場合は真を返す。
+    /// fgが真ならジョブをフォアグラウンドに設定し、偽ならバックグラウンドのまま端末はシェルが持つ。
+    fn spawn_child(&mut self, line: &str, cmd: &[Stage], fg: bool) -> bool {
+        assert_ne!(cmd.len(), 0); // コマンドが空でないか検査
+
+        // ジョブIDを取得
+        let job_id = if let Some(id) = self.get_new_job_id() {
+            id
+        } else {
+            eprintln!("ZeroSh: 管理可能なジョブを最大値に到達。");
+            return false;
+        };
+
+        // コマンド間をつなぐパイプを作成。
+        // i番目のパイプは、i番目のプロセスの標準出力とi+1番目のプロセスの標準入力をつなぐ。
+        let mut pipes = Vec::with_capacity(cmd.len() - 1);
+        for _ in 1..cmd.len() {
+            match pipe2(OFlag::O_CLOEXEC) {
+                Ok(p) => pipes.push(p), // (読み取り側, 書き込み側)
+                Err(e) => {
+                    eprintln!("ZeroSh: パイプ生成エラー: {}", e);
+                    close_pipes(&pipes);
+                    return false;
+                }
+            }
+        }
+
+        // 親プロセス側のパイプを全て閉じる関数を定義
+        let cleanup_pipe = CleanUp {
+            f: || close_pipes(&pipes),
+        };
+
+        let mut pgid = Pid::from_raw(0); // from_rawの引数に0を入れると自動でpgidを割り当てる
+        let mut pids = HashMap::new();
+        let mut last = pgid; // 最後のプロセスのプロセスID
+        for (i, stage) in cmd.iter().enumerate() {
+            let input = if i > 0 { Some(pipes[i - 1].0) } else { None };
+            let output = pipes.get(i).map(|p| p.1);
+
+            // サブシェル内ではジョブ制御を行わないため、プロセスグループを変更しない
+            let new_pgid = if self.job_control { Some(pgid) } else { None };
+            let result = match stage {
+                Stage::Exec(c) => {
+                    // PATH=... cmdのように代入されていれば、そのPATHから検索
+                    let path = match c.assigns.iter().rev().find(|(name, _)| name == "PATH") {
+                        Some((_, value)) => Some(value.as_str()),
+                        None => self.vars.get("PATH"),
+                    };
+                    let env = self.vars.environ(&c.assigns);
+                    fork_exec(new_pgid, c, path, &env, input, output, &pipes)
+                }
+                _ => self.fork_subshell(new_pgid, input, output, &pipes, |w| w.exec_stage(stage)),
+            };
+
+            match result {
+                Ok(child) => {
+                    if i == 0 {
+                        pgid = child; // 1つ目のプロセスのプロセスIDがプロセスグループIDとなる
+                    }
+                    last = child;
+
+                    // プロセスの情報を追加
+                    let info = ProcInfo {
+                        state: ProcState::Run,
+                        pgid,
+                        index: i,
+                    };
+                    pids.insert(child, info);
+                }
+                Err(e) => {
+                    eprintln!("ZeroSh: プロセス生成エラー: {}", e);
+                    if i > 0 {
+                        // 生成済みのプロセスは終了させ、後でwait_childで回収する
+                        for pid in pids.keys() {
+                            let _ = kill(*pid, Signal::SIGKILL);
+                        }
+                    }
+                    return false;
+                }
+            }
+        }
+
+        std::mem::drop(cleanup_pipe); // パイプをクローズ
+
+        self.insert_job(job_id, pgid, pids, line);
+        if fg {
+            // 子プロセスをフォアグラウンドプロセスグループにする
+            self.fg = Some(pgid);
+            if self.job_control {
+                tcsetpgrp(libc::STDIN_FILENO, pgid).unwrap();
+            }
+        } else {
+            self.last_bg = Some(last);
+            if self.job_control {
+                eprintln!("[{}] {}", job_id, pgid);
+            }
+        }
+
+        true
+    }
+
+    /// サブシェルとしてfork。子プロセスではfを実行し、その終了コードで終了する。
+    /// 引数の意味はfork_execと同じ。
+    fn fork_subshell<F>(
+        &mut self,
+        pgid: Option<Pid>,
+        input: Option<RawFd>,
+        output: Option<RawFd>,
+        pipes: &[(RawFd, RawFd)],
+        f: F,
+    ) -> Result<Pid, DynError>
+    where
+        F: FnOnce(&mut Worker) -> i32,
+    {
+        match syscall(|| unsafe { fork() })? {
+            ForkResult::Parent { child, .. } => {
+                if let Some(pgid) = pgid {
+                    let _ = setpgid(child, pgid);
+                }
+                Ok(child)
+            }
+            ForkResult::Child => {
+                if let Some(pgid) = pgid {
+                    setpgid(Pid::from_raw(0), pgid).unwrap();
+                }
+
+                // サブシェルではシグナルを受信するスレッドが存在しないため、デフォルトの動作に戻す
+                reset_signals();
+                set_stdio(input, output, pipes);
+
+                // ジョブ制御を行わず、親のジョブ情報も引き継がない
+                self.job_control = false;
+                self.notifier.printer = Arc::default(); // 親のシェルの入力行に書き込まない
+                self.fg = None;
+                self.jobs.clear();
+                self.job_stack.clear();
+                self.pgid_to_pids.clear();
+                self.pid_to_info.clear();
+                self.pgid_to_status.clear();
+                self.job_tmodes.clear();
+                self.finished_pids.clear();
+                self.finished_jobs.clear();
+
+                let status = f(self);
+                exit(self.exiting.unwrap_or(status));
+            }
+        }
+    }
+
+    /// フォアグラウンドのジョブが終了、または停止するまで待機。
+    fn wait_fg(&mut self) {
+        while self.fg.is_some() {
+            // Ctrl+Cはフォアグラウンドのジョブにも送られるため、ジョブの終了を待ち続ける
+            if self.wait_event() == Waited::Closed {
+                return;
+            }
+        }
+    }
+
+    /// exitコマンドを実行
+    ///
+    /// ジョブがある場合、1回目は警告して終了しない。
+    /// 間に他のコマンドを挟まずに再度実行された場合は、ジョブにSIGHUPを送信して終了する。
+    fn run_exit(&mut self, args: &[String]) -> i32 {
+        if self.job_control && !self.jobs.is_empty() {
+            let warned = self
+                .exit_warned
+                .is_some_and(|n| n == self.cmd_count || n + 1 == self.cmd_count);
+            if !warned {
+                let stopped = self
+                    .jobs
+                    .values()
+                    .any(|(pgid, _)| self.is_group_stop(*pgid) == Some(true));
+                if stopped {
+                    eprintln!("停止中のジョブがあります。");
+                } else {
+                    eprintln!("実行中のジョブがあります。");
+                }
+                self.exit_warned = Some(self.cmd_count);
+                return 1; // 失敗
+            }
+            self.hangup_jobs();
+        }
+
+        // 終了コードを取得
+        let exit_val = if let Some(s) = args.get(1) {
+            if let Ok(n) = s.parse::<i32>() {
+                n
+            } else {
+                // 終了コードが整数ではない（i32のparseに失敗)
+                eprintln!("{}は不正な引数です。", s);
+                return 1;
+            }
+        } else {
+            self.exit_val
+        };
+
+        self.exiting = Some(exit_val);
+        exit_val
+    }
+
+    /// setコマンドを実行。
+    ///
+    /// - set: 変数の一覧を表示
+    /// - set -o NAME、set +o NAME: オプションを有効、無効にする
+    /// - set -o、set +o: オプションの一覧を表示
+    /// - set -b: set -o notifyと同じ
+    /// - set -- ARG...、set ARG...: 位置パラメータを設定
+    fn run_set(&mut self, args: &[String]) -> i32 {
+        if args.len() < 2 {
+            for (name, var) in self.vars.iter() {
+                if let Some(value) = &var.value {
+                    println!("{}={}", name, quote_value(value));
+                }
+            }
+            return 0;
+        }
+
+        let mut i = 1;
+        while i < args.len() {
+            let arg = args[i].as_str();
+            if arg == "--" {
+                self.positional = args[i + 1..].to_vec();
+                return 0;
+            }
+            let (on, flags) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+                (Some(flags), _) if !flags.is_empty() => (true, flags),
+                (_, Some(flags)) if !flags.is_empty() => (false, flags),
+                (None, None) => {
+                    // オプション以降の引数は位置パラメータ
+                    self.positional = args[i..].to_vec();
+                    return 0;
+                }
+                _ => {
+                    eprintln!("set: {}: 不正な引数です。", arg);
+                    return 2;
+                }
+            };
+
+            for c in flags.chars() {
+                let name = match c {
+                    'b' => "notify",
+                    'o' => match args.get(i + 1) {
+                        Some(name) => {
+                            i += 1;
+                            name.as_str()
+                        }
+                        None => {
+                            self.print_options(!on);
+                            return 0;
+                        }
+                    },
+                    _ => {
+                        eprintln!("set: -{}: 不正なオプションです。", c);
+                        eprintln!("usage: set [-b] [-o option] [+b] [+o option]");
+                        return 2;
+                    }
+                };
+
+                if !self.set_option(name, on) {
+                    eprintln!("set: {}: 不正なオプション名です。", name);
+                    return 2;
+                }
+            }
+            i += 1;
+        }
+        0
+    }
+
+    /// シェルのオプションを設定。存在しないオプションの場合は偽を返す。
+    fn set_option(&mut self, name: &str, on: bool) -> bool {
+        match name {
+            "notify" => self.notifier.immediate.store(on, Ordering::SeqCst),
+            "pipefail" => self.pipefail = on,
+            _ => return false,
+        }
+        true
+    }
+
+    /// shoptコマンドを実行。
+    ///
+    /// - shopt: オプションの一覧を表示
+    /// - shopt -s NAME...、shopt -u NAME...: オプションを有効、無効にする
+    /// - shopt -s、shopt -u: 有効、無効なオプションの一覧を表示
+    /// - shopt -p [NAME...]: shopt -sのように再入力できる形式で表示
+    /// - shopt [-q] NAME...: オプションを表示し、全て有効なら0を返す。-qなら表示しない
+    fn run_shopt(&mut self, args: &[String]) -> i32 {
+        let (mut set, mut reusable, mut quiet) = (None, false, false);
+        let mut i = 1;
+        while let Some(opt) = args.get(i).filter(|a| a.starts_with('-')) {
+            i += 1;
+            match opt.as_str() {
+                "-s" => set = Some(true),
+                "-u" => set = Some(false),
+                "-p" => reusable = true,
+                "-q" => quiet = true,
+                "--" => break,
+                _ => {
+                    eprintln!("shopt: {}: 不正なオプションです。", opt);
+                    eprintln!("usage: shopt [-pqsu] [optname ...]");
+                    return 2;
+                }
+            }
+        }
+
+        let explicit = i < args.len(); // オプション名が指定されたなら真
+        let names: Vec<&str> = if explicit {
+            args[i..].iter().map(|s| s.as_str()).collect()
+        } else {
+            SHOPT_NAMES.to_vec()
+        };
+
+        let mut status = 0;
+        for name in names {
+            let Some(opt) = self.shopt_mut(name) else {
+                eprintln!("shopt: {}: 不正なオプション名です。", name);
+                status = 1;
+                continue;
+            };
+            if let Some(on) = set
+                && explicit
+            {
+                *opt = on;
+                self.extglob.store(self.glob_opts.extglob, Ordering::SeqCst);
+                continue;
+            }
+
+            let on = *opt;
+            if explicit && !on {
+                status = 1;
+            }
+            if quiet || set.is_some_and(|s| s != on) {
+                continue;
+            }
+            if reusable {
+                println!("shopt {} {}", if on { "-s" } else { "-u" }, name);
+            } else {
+                println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+            }
+        }
+        status
+    }
+
+    /// shoptのオプションの値への参照。存在しないオプションの場合はNone。
+    fn shopt_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let opts = &mut self.glob_opts;
+        let opt = match name {
+            "dotglob" => &mut opts.dotglob,
+            "extglob" => &mut opts.extglob,
+            "failglob" => &mut opts.failglob,
+            "globstar" => &mut opts.globstar,
+            "nocaseglob" => &mut opts.nocaseglob,
+            "nullglob" => &mut opts.nullglob,
+            _ => return None,
+        };
+        Some(opt)
+    }
+
+    /// $-で展開される、有効なオプションの文字。
+    fn option_flags(&self) -> String {
+        let mut flags = String::new();
+        if self.notifier.immediate() {
+            flags.push('b');
+        }
+        flags.push('i'); // 常に対話シェル
+        if self.job_control {
+            flags.push('m');
+        }
+        flags
+    }
+
+    /// testコマンド、[コマンドを実行。真なら0、偽なら1、式が不正なら2を返す。
+    fn run_test(&mut self, args: &[String]) -> i32 {
+        let name = args[0].as_str();
+        let mut rest: Vec<&str> = args[1..].iter().map(|s| s.as_str()).collect();
+        if name == "[" && rest.pop() != Some("]") {
+            eprintln!("[: `]'が必要です。");
+            return 2;
+        }
+
+        match cond::test(&rest) {
+            Ok(result) => !result as i32,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                2
+            }
+        }
+    }
+
+    /// シェルのオプションの一覧を表示。
+    /// reusableが真なら、set +oのように再入力できる形式で表示する。
+    fn print_options(&self, reusable: bool) {
+        let options = [
+            ("notify", self.notifier.immediate()),
+            ("pipefail", self.pipefail),
+        ];
+        for (name, on) in options {
+            if reusable {
+                println!("set {}o {}", if on { '-' } else { '+' }, name);
+            } else {
+                println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+            }
+        }
+    }
+
+    /// exportコマンドを実行。
+    ///
+    /// - export NAME[=value]...: 変数をエクスポートし、子プロセスの環境変数とする
+    /// - export -n NAME[=value]...: エクスポートを解除
+    /// - export、export -p: エクスポートされた変数の一覧を表示
+    fn run_export(&mut self, args: &[String]) -> i32 {
+        let mut export = true;
+        let mut i = 1;
+        while let Some(opt) = args.get(i).filter(|a| a.starts_with('-')) {
+            i += 1;
+            match opt.as_str() {
+                "-n" => export = false,
+                "-p" => (),
+                "--" => break,
+                _ => {
+                    eprintln!("export: {}: 不正なオプションです。", opt);
+                    eprintln!("usage: export [-n] [-p] [name[=value] ...]");
+                    return 2;
+                }
+            }
+        }
+
+        if i >= args.len() {
+            self.print_declared(|var| var.exported);
+            return 0;
+        }
+        self.declare("export", &args[i..], |vars, name| vars.export(name, export))
+    }
+
+    /// readonlyコマンドを実行。
+    ///
+    /// - readonly NAME[=value]...: 変数を読み取り専用にする
+    /// - readonly、readonly -p: 読み取り専用の変数の一覧を表示
+    fn run_readonly(&mut self, args: &[String]) -> i32 {
+        let mut i = 1;
+        while let Some(opt) = args.get(i).filter(|a| a.starts_with('-')) {
+            i += 1;
+            match opt.as_str() {
+                "-p" => (),
+                "--" => break,
+                _ => {
+                    eprintln!("readonly: {}: 不正なオプションです。", opt);
+                    eprintln!("usage: readonly [-p] [name[=value] ...]");
+                    return 2;
+                }
+            }
+        }
+
+        if i >= args.len() {
+            self.print_declared(|var| var.readonly);
+            return 0;
+        }
+        self.declare("readonly", &args[i..], |vars, name| vars.set_readonly(name))
+    }
+
+    /// export、readonlyの引数のNAME[=value]を順に代入し、変数にfで属性を設定。
+    fn declare<F>(&mut self, cmd: &str, args: &[String], f: F) -> i32
+    where
+        F: Fn(&mut Vars, &str),
+    {
+        let mut status = 0;
+        for arg in args.iter() {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                eprintln!("{}: {}: 変数名として不正です。", cmd, arg);
+                status = 1;
+                continue;
+            }
+            if let Some(value) = value
+                && let Err(e) = self.vars.set(name, value.to_string())
+            {
+                eprintln!("{}: {}", cmd, e);
+                status = 1;
+                continue;
+            }
+            f(&mut self.vars, name);
+        }
+        status
+    }
+
+    /// filterが真となる変数を、declareコマンドの形式で表示。
+    fn print_declared<F>(&self, filter: F)
+    where
+        F: Fn(&Var) -> bool,
+    {
+        for (name, var) in self.vars.iter().filter(|(_, var)| filter(var)) {
+            let mut flags = String::from("-");
+            if var.readonly {
+                flags.push('r');
+            }
+            if var.exported {
+                flags.push('x');
+            }
+
+            match &var.value {
+                Some(value) => println!("declare {} {}=\"{}\"", flags, name, escape_dquote(value)),
+                None => println!("declare {} {}", flags, name),
+            }
+        }
+    }
+
+    /// unsetコマンドを実行。
+    ///
+    /// - unset [-v] NAME...: 変数を削除
+    fn run_unset(&mut self, args: &[String]) -> i32 {
+        let mut i = 1;
+        while let Some(opt) = args.get(i).filter(|a| a.starts_with('-')) {
+            i += 1;
+            match opt.as_str() {
+                "-v" => (),
+                "--" => break,
+                _ => {
+                    eprintln!("unset: {}: 不正なオプションです。", opt);
+                    eprintln!("usage: unset [-v] [name ...]");
+                    return 2;
+                }
+            }
+        }
+
+        let mut status = 0;
+        for name in args[i..].iter() {
+            if !is_name(name) {
+                eprintln!("unset: {}: 変数名として不正です。", name);
+                status = 1;
+            } else if let Err(e) = self.vars.unset(name) {
+                eprintln!("unset: {}", e);
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// letコマンドを実行。
+    ///
+    /// - let EXPR...: 算術式を順に評価し、最後の式の値が0以外なら0、0なら1を返す
+    fn run_let(&mut self, args: &[String]) -> i32 {
+        if args.len() < 2 {
+            eprintln!("let: 式が必要です。");
+            return 1;
+        }
+
+        let mut value = 0;
+        for expr in args[1..].iter() {
+            match arith::eval(expr, &mut self.vars) {
+                Ok(n) => value = n,
+                Err(e) => {
+                    eprintln!("let: {}", e);
+                    return 1;
+                }
+            }
+        }
+        (value == 0) as i32
+    }
+
+    /// cdコマンドを実行。
+    ///
+    /// - cd: ホームディレクトリに移動
+    /// - cd DIR: DIRに移動
+    /// - cd -: 直前のディレクトリに移動し、移動先を表示
+    fn run_cd(&mut self, args: &[String]) -> i32 {
+        let var = |w: &Worker, name: &str| match w.vars.get(name) {
+            Some(dir) => Ok(dir.to_string()),
+            None => Err(format!("cd: {}が設定されていません。", name)),
+        };
+        let dir = match args.get(1).map(|s| s.as_str()) {
+            None => var(self, "HOME"),
+            Some("-") => var(self, "OLDPWD"),
+            Some(dir) => Ok(dir.to_string()),
+        };
+        let dir = match dir {
+            Ok(dir) => dir,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+
+        let cwd = || {
+            std::env::current_dir()
+                .ok()
+                .map(|p| p.to_string_lossy().into_owned())
+        };
+        let old = cwd();
+        if let Err(e) = std::env::set_current_dir(&dir) {
+            eprintln!("cd: {}: {}", dir, e);
+            return 1;
+        }
+        if args.get(1).is_some_and(|a| a == "-") {
+            println!("{}", dir);
+        }
+
+        // 移動前と移動後のディレクトリを変数に設定
+        let mut status = 0;
+        for (name, value) in [("OLDPWD", old), ("PWD", cwd())] {
+            if let Some(value) = value
+                && let Err(e) = self.vars.set(name, value)
+            {
+                eprintln!("cd: {}", e);
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// disownコマンドを実行。
+    ///
+    /// - disown [%job]...: ジョブをジョブテーブルから削除
+    /// - disown -h [%job]...: ジョブテーブルには残し、終了時にSIGHUPを送信しない
+    /// - disown -a: 全てのジョブが対象
+    fn run_disown(&mut self, args: &[String]) -> i32 {
+        let (mut nohup, mut all) = (false, false);
+        let mut specs = Vec::new();
+        for arg in args[1..].iter() {
+            match arg.strip_prefix('-') {
+                Some(flags) if !flags.is_empty() && specs.is_empty() => {
+                    for c in flags.chars() {
+                        match c {
+                            'h' => nohup = true,
+                            'a' => all = true,
+                            _ => {
+                                eprintln!("disown: -{}: 不正なオプションです。", c);
+                                eprintln!("usage: disown [-ah] [jobspec ...]");
+                                return 2;
+                            }
+                        }
+                    }
+                }
+                _ => specs.push(arg.as_str()),
+            }
+        }
+
+        let mut job_ids = Vec::new();
+        let mut status = 0;
+        if all {
+            job_ids.extend(self.jobs.keys().copied());
+        } else {
+            if specs.is_empty() {
+                specs.push("%+");
+            }
+            for spec in specs {
+                match self.resolve_job(spec) {
+                    Ok(job_id) => job_ids.push(job_id),
+                    Err(e) => {
+                        eprintln!("disown: {}", e);
+                        status = 1;
+                    }
+                }
+            }
+        }
+
+        for job_id in job_ids {
+            let pgid = self.jobs[&job_id].0;
+            if nohup {
+                self.nohup_pgids.insert(pgid);
+            } else {
+                // プロセスの情報も削除し、終了しても管理しない
+                if let Some((_, pids)) = self.pgid_to_pids.get_mut(&pgid) {
+                    for pid in pids.drain() {
+                        self.pid_to_info.remove(&pid);
+                    }
+                }
                 self.remove_job(job_id);
-                self.set_shell_fg(shell_tx);
-            } else if self.is_group_stop(pgid).unwrap() {
-                // フォアグラウンドプロセスが全て停止中の場合、シェルをフォアグラウンドに設定
-                eprintln!("\n[{}] 停止\t{}", job_id, line);
-                self.set_shell_fg(shell_tx);
             }
+        }
+        status
+    }
+
+    /// disown -hされていない全てのジョブにSIGHUPを送信。停止中のジョブにはSIGCONTも送信する。
+    fn hangup_jobs(&self) {
+        for (pgid, _) in self.jobs.values() {
+            if self.nohup_pgids.contains(pgid) {
+                continue;
+            }
+            let _ = killpg(*pgid, Signal::SIGHUP);
+            if self.is_group_stop(*pgid) == Some(true) {
+                let _ = killpg(*pgid, Signal::SIGCONT);
+            }
+        }
+    }
+
+    /// jobsコマンドを実行
+    ///
+    /// - -l: プロセスIDも表示
+    /// - -p: プロセスグループIDのみ表示
+    /// - -r: 実行中のジョブのみ表示
+    /// - -s: 停止中のジョブのみ表示
+    fn run_jobs(&self, args: &[String]) -> i32 {
+        let (mut long, mut pgid_only, mut running, mut stopped) = (false, false, false, false);
+        let mut specs = Vec::new();
+        for arg in args[1..].iter() {
+            match arg.strip_prefix('-') {
+                Some(flags) if !flags.is_empty() && specs.is_empty() => {
+                    for c in flags.chars() {
+                        match c {
+                            'l' => long = true,
+                            'p' => pgid_only = true,
+                            'r' => running = true,
+                            's' => stopped = true,
+                            _ => {
+                                eprintln!("jobs: -{}: 不正なオプションです。", c);
+                                eprintln!("usage: jobs [-lprs] [jobspec ...]");
+                                return 2;
+                            }
+                        }
+                    }
+                }
+                _ => specs.push(arg.as_str()),
+            }
+        }
+
+        // 表示するジョブを取得。指定がなければ全てのジョブ
+        let mut job_ids = Vec::new();
+        let mut status = 0;
+        if specs.is_empty() {
+            job_ids.extend(self.jobs.keys().copied());
+        } else {
+            for spec in specs {
+                match self.resolve_job(spec) {
+                    Ok(job_id) => job_ids.push(job_id),
+                    Err(e) => {
+                        eprintln!("jobs: {}", e);
+                        status = 1;
+                    }
+                }
+            }
+        }
+
+        for job_id in job_ids {
+            let pgid = self.jobs[&job_id].0;
+            let is_stop = self.is_group_stop(pgid).unwrap();
+            if (running && is_stop) || (stopped && !is_stop) {
+                continue;
+            }
+
+            if pgid_only {
+                println!("{}", pgid);
+            } else if long {
+                // プロセスグループIDのプロセスから順に表示
+                let mut pids: Vec<Pid> = self.pgid_to_pids[&pgid].1.iter().copied().collect();
+                pids.sort_by_key(|pid| (*pid != pgid, pid.as_raw()));
+                let head = format!("[{}]{}", job_id, self.job_marker(job_id));
+                for (i, pid) in pids.iter().enumerate() {
+                    let state = match self.pid_to_info[pid].state {
+                        ProcState::Run => "Running",
+                        ProcState::Stop => "Stopped",
+                    };
+                    if i == 0 {
+                        let line = self.job_line(job_id, !is_stop);
+                        println!("{} {:>5} {:<24}{}", head, pid, state, line);
+                    } else {
+                        println!("{:w$} {:>5} {}", "", pid, state, w = head.len());
+                    }
+                }
+            } else {
+                let state = if is_stop { "Stopped" } else { "Running" };
+                println!("{}", self.format_job(job_id, state));
+            }
+        }
+        status
+    }
+
+    /// ジョブの状態を「[1]+  Stopped                 vim foo」の形式で返す。
+    fn format_job(&self, job_id: usize, state: &str) -> String {
+        format!(
+            "[{}]{}  {:<24}{}",
+            job_id,
+            self.job_marker(job_id),
+            state,
+            self.job_line(job_id, state == "Running")
+        )
+    }
+
+    /// ジョブの実行コマンド。実行中のジョブには末尾に&を付ける。
+    fn job_line(&self, job_id: usize, running: bool) -> String {
+        let line = &self.jobs[&job_id].1;
+        if running {
+            format!("{} &", line)
+        } else {
+            line.clone()
+        }
+    }
+
+    /// カレントジョブなら+、1つ前のジョブなら-、それ以外は空白。
+    fn job_marker(&self, job_id: usize) -> char {
+        let mut it = self.job_stack.iter().rev();
+        if it.next() == Some(&job_id) {
+            '+'
+        } else if it.next() == Some(&job_id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// fgコマンドを実行。
+    fn run_fg(&mut self, args: &[String]) -> i32 {
+        let Some((job_id, pgid, line)) = self.get_job_arg(args) else {
+            return 1;
+        };
+        eprintln!("[{}] 再開\t{}", job_id, line);
+        self.touch_job(job_id);
+
+        // フォアグラウンドプロセスに設定し、停止した時の端末の設定を戻す
+        self.fg = Some(pgid);
+        tcsetpgrp(libc::STDIN_FILENO, pgid).unwrap();
+        if let Some(tmodes) = self.job_tmodes.get(&pgid) {
+            let _ = tcsetattr(libc::STDIN_FILENO, SetArg::TCSADRAIN, tmodes);
+        }
+
+        // ジョブの実行を再開し、終了か停止まで待機
+        killpg(pgid, Signal::SIGCONT).unwrap();
+        self.set_group_run(pgid);
+        self.wait_fg();
+        self.exit_val
+    }
+
+    /// bgコマンドを実行。停止中のジョブをバックグラウンドで再開する。
+    fn run_bg(&mut self, args: &[String]) -> i32 {
+        let Some((job_id, pgid, line)) = self.get_job_arg(args) else {
+            return 1;
+        };
+        if !self.is_group_stop(pgid).unwrap() {
+            eprintln!("bg: ジョブ{}は既にバックグラウンドで実行中です。", job_id);
+            return 0;
+        }
+        self.touch_job(job_id);
+
+        // 端末は渡さずに実行を再開
+        if let Err(e) = killpg(pgid, Signal::SIGCONT) {
+            eprintln!("ZeroSh: ジョブの再開に失敗: {}", e);
+            return 1;
+        }
+        self.set_group_run(pgid);
+
+        println!("[{}] {} &", job_id, line);
+        0
+    }
+
+    /// killコマンドを実行。ジョブ指定の場合はプロセスグループ全体にシグナルを送信する。
+    ///
+    /// - kill [-s NAME | -n NUM | -NAME | -NUM] (pid | %job)...
+    /// - kill -l [NUM | NAME]...
+    fn run_kill(&mut self, args: &[String]) -> i32 {
+        let mut sig = Some(Signal::SIGTERM);
+        let mut i = 1;
+        match args.get(i).map(|s| s.as_str()) {
+            Some("-l" | "-L") => return list_signals(&args[2..]),
+            Some(opt @ ("-s" | "-n")) => {
+                let Some(name) = args.get(i + 1) else {
+                    eprintln!("kill: {}: 引数が必要です。", opt);
+                    return 2;
+                };
+                match parse_signal(name) {
+                    Ok(s) => sig = s,
+                    Err(e) => {
+                        eprintln!("kill: {}", e);
+                        return 1;
+                    }
+                }
+                i += 2;
+            }
+            Some("--") => i += 1,
+            Some(opt) if opt.len() > 1 && opt.starts_with('-') => {
+                match parse_signal(&opt[1..]) {
+                    Ok(s) => sig = s,
+                    Err(e) => {
+                        eprintln!("kill: {}", e);
+                        return 1;
+                    }
+                }
+                i += 1;
+            }
+            _ => (),
+        }
+
+        if i >= args.len() {
+            eprintln!(
+                "usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]"
+            );
+            return 2;
+        }
+
+        let mut status = 0;
+        for arg in args[i..].iter() {
+            let result = if arg.starts_with('%') {
+                match self.resolve_job(arg) {
+                    Ok(job_id) => {
+                        let pgid = self.jobs[&job_id].0;
+                        let result = killpg(pgid, sig);
+
+                        // 停止中のジョブは、SIGCONTを送らないと終了のシグナルを処理できない
+                        if result.is_ok()
+                            && matches!(sig, Some(Signal::SIGTERM | Signal::SIGHUP))
+                            && self.is_group_stop(pgid) == Some(true)
+                        {
+                            let _ = killpg(pgid, Signal::SIGCONT);
+                        }
+                        result.map_err(|e| format!("{}: {}", arg, e))
+                    }
+                    Err(e) => Err(e),
+                }
+            } else if let Ok(pid) = arg.parse::<i32>() {
+                kill(Pid::from_raw(pid), sig).map_err(|e| format!("({}) - {}", pid, e))
+            } else {
+                Err(format!(
+                    "{}: 引数はプロセスIDかジョブ指定でなければなりません。",
+                    arg
+                ))
+            };
+
+            if let Err(e) = result {
+                eprintln!("kill: {}", e);
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// waitコマンドを実行。
+    ///
+    /// - wait: 全てのバックグラウンドのジョブの終了を待つ
+    /// - wait (pid | %job)...: 指定したプロセスかジョブの終了を待ち、最後の終了コードを返す
+    /// - wait -n: いずれか1つのジョブの終了を待ち、その終了コードを返す
+    fn run_wait(&mut self, args: &[String]) -> i32 {
+        if args.get(1).map(|s| s.as_str()) == Some("-n") {
+            return self.wait_any_job();
+        }
+
+        if args.len() < 2 {
+            // 停止中のジョブは終了しないため、実行中のジョブがなくなるまで待つ
+            let status = self.wait_until(|w| {
+                w.jobs
+                    .values()
+                    .all(|(pgid, _)| w.is_group_stop(*pgid) == Some(true))
+            });
+            if status.is_none() {
+                for (pgid, _) in self.finished_jobs.drain(..) {
+                    self.notifier.cancel(pgid);
+                }
+                self.finished_pids.clear();
+            }
+            return status.unwrap_or(0);
+        }
+
+        let mut status = 0;
+        for arg in args[1..].iter() {
+            status = if arg.starts_with('%') {
+                match self.resolve_job(arg) {
+                    Ok(job_id) => self.wait_job(self.jobs[&job_id].0),
+                    Err(e) => {
+                        eprintln!("wait: {}", e);
+                        127
+                    }
+                }
+            } else if let Ok(pid) = arg.parse::<i32>() {
+                self.wait_pid(Pid::from_raw(pid))
+            } else {
+                eprintln!(
+                    "wait: {}: 引数はプロセスIDかジョブ指定でなければなりません。",
+                    arg
+                );
+                2
+            };
+        }
+        status
+    }
+
+    /// プロセスグループpgidのジョブが終了するまで待ち、終了コードを返す。
+    fn wait_job(&mut self, pgid: Pid) -> i32 {
+        if let Some(status) = self.wait_until(|w| !w.pgid_to_pids.contains_key(&pgid)) {
+            return status;
+        }
+
+        self.finished_pids.retain(|(_, g, _)| *g != pgid);
+        self.reap_job(pgid).unwrap_or(127)
+    }
+
+    /// プロセスが終了するまで待ち、終了コードを返す。
+    fn wait_pid(&mut self, pid: Pid) -> i32 {
+        let pgid = match self.pid_to_info.get(&pid) {
+            Some(info) => info.pgid,
+            None => match self.finished_pids.iter().find(|(p, _, _)| *p == pid) {
+                Some((_, pgid, _)) => *pgid,
+                None => {
+                    eprintln!("wait: pid {}はこのシェルの子プロセスではありません。", pid);
+                    return 127;
+                }
+            },
+        };
+
+        if let Some(status) = self.wait_until(|w| !w.pid_to_info.contains_key(&pid)) {
+            return status;
+        }
+        self.reap_job(pgid);
+        match self.finished_pids.iter().position(|(p, _, _)| *p == pid) {
+            Some(i) => self.finished_pids.remove(i).unwrap().2,
+            None => 127,
+        }
+    }
+
+    /// 終了したジョブをwaitの対象から外し、表示されていない通知も取り消す。
+    /// ジョブが終了していればその終了コードを返す。
+    fn reap_job(&mut self, pgid: Pid) -> Option<i32> {
+        let i = self.finished_jobs.iter().position(|(g, _)| *g == pgid)?;
+        self.notifier.cancel(pgid);
+        self.finished_jobs.remove(i).map(|(_, status)| status)
+    }
+
+    /// いずれか1つのジョブが終了するまで待ち、終了コードを返す。
+    /// 既に終了したがwaitされていないジョブがあれば、その終了コードを返す。
+    fn wait_any_job(&mut self) -> i32 {
+        if self.finished_jobs.is_empty() && self.jobs.is_empty() {
+            return 127;
+        }
+
+        if let Some(status) = self.wait_until(|w| !w.finished_jobs.is_empty() || w.jobs.is_empty())
+        {
+            return status;
+        }
+        match self.finished_jobs.front() {
+            Some((pgid, _)) => self.reap_job(*pgid).unwrap(),
+            None => 127,
+        }
+    }
+
+    /// doneが真になるまで子プロセスの状態変化を処理しながら待機。
+    /// SIGINTにより中断された場合はSome(終了コード)を返す。
+    fn wait_until<F>(&mut self, done: F) -> Option<i32>
+    where
+        F: Fn(&Worker) -> bool,
+    {
+        while !done(self) {
+            match self.wait_event() {
+                Waited::Changed => (),
+                Waited::Interrupted => return Some(128 + SIGINT),
+                Waited::Closed => break,
+            }
+        }
+        None
+    }
+
+    /// 子プロセスの状態変化か、シェルへのCtrl+Cを1つ待って処理する。
+    ///
+    /// サブシェルではSIGCHLDを受信するスレッドがないため、waitでブロックする。
+    fn wait_event(&mut self) -> Waited {
+        if !self.job_control {
+            if self.pid_to_info.is_empty() {
+                return Waited::Closed;
+            }
+            self.wait_child(true);
+            return Waited::Changed;
+        }
+
+        match self.recv() {
+            Some(WorkerMsg::Signal(SIGCHLD | SIGTSTP)) => self.wait_child(false),
+            Some(WorkerMsg::Signal(SIGINT)) => {
+                // シェル自身がフォアグラウンドの間、つまりコマンドの合間に押されたCtrl+C
+                self.interrupted = true;
+                return Waited::Interrupted;
+            }
+            Some(_) => (),
+            None => return Waited::Closed,
+        }
+        Waited::Changed
+    }
+
+    /// fg、bgの引数のジョブを（ジョブID, プロセスグループID, 実行コマンド）として取得。
+    /// 省略された場合はカレントジョブ。見つからない場合はエラーを表示してNoneを返す。
+    fn get_job_arg(&self, args: &[String]) -> Option<(usize, Pid, String)> {
+        if !self.job_control {
+            eprintln!("{}: ジョブ制御が無効です。", args[0]);
+            return None;
+        }
+
+        let spec = args.get(1).map(|s| s.as_str()).unwrap_or("%+");
+        match self.resolve_job(spec) {
+            Ok(job_id) => {
+                let (pgid, line) = self.jobs.get(&job_id).unwrap();
+                Some((job_id, *pgid, line.clone()))
+            }
+            Err(e) => {
+                eprintln!("{}: {}", args[0], e);
+                None
+            }
+        }
+    }
+
+    /// ジョブ指定をジョブIDに変換。先頭の%は省略可能。
+    ///
+    /// - %n: ジョブ番号n
+    /// - %%、%+: カレントジョブ
+    /// - %-: 1つ前のジョブ
+    /// - %str: 実行コマンドがstrから始まるジョブ
+    /// - %?str: 実行コマンドにstrを含むジョブ
+    fn resolve_job(&self, spec: &str) -> Result<usize, String> {
+        let body = spec.strip_prefix('%').unwrap_or(spec);
+        let not_found = || format!("{}というジョブは見つかりませんでした。", spec);
+
+        match body {
+            "" | "%" | "+" => match self.job_stack.last() {
+                Some(id) => Ok(*id),
+                None => Err("カレントジョブがありません。".to_string()),
+            },
+            "-" => {
+                // ジョブが1つしかない場合はカレントジョブ
+                let i = self.job_stack.len().saturating_sub(2);
+                self.job_stack.get(i).copied().ok_or_else(not_found)
+            }
+            _ if body.chars().all(|c| c.is_ascii_digit()) => match body.parse::<usize>() {
+                Ok(n) if self.jobs.contains_key(&n) => Ok(n),
+                _ => Err(not_found()),
+            },
+            _ => {
+                let found: Vec<usize> = match body.strip_prefix('?') {
+                    Some(sub) => self
+                        .jobs
+                        .iter()
+                        .filter(|(_, (_, line))| line.contains(sub))
+                        .map(|(id, _)| *id)
+                        .collect(),
+                    None => self
+                        .jobs
+                        .iter()
+                        .filter(|(_, (_, line))| line.starts_with(body))
+                        .map(|(id, _)| *id)
+                        .collect(),
+                };
+                match found.as_slice() {
+                    [id] => Ok(*id),
+                    [] => Err(not_found()),
+                    _ => Err(format!("{}: 曖昧なジョブ指定です。", spec)),
+                }
+            }
+        }
+    }
+
+    /// ジョブをカレントジョブにする。それまでのカレントジョブは1つ前のジョブとなる。
+    fn touch_job(&mut self, job_id: usize) {
+        self.job_stack.retain(|id| *id != job_id);
+        self.job_stack.push(job_id);
+    }
+
+    /// プロセスグループ内の全プロセスを実行中に設定。
+    fn set_group_run(&mut self, pgid: Pid) {
+        let pids: Vec<Pid> = match self.pgid_to_pids.get(&pgid) {
+            Some((_, pids)) => pids.iter().copied().collect(),
+            None => return,
+        };
+        for pid in pids {
+            self.process_continue(pid);
+        }
+    }
+
+    /// 子プロセスの状態変化を管理。
+    /// blockが真なら状態変化があるまで待機し、1つの状態変化を処理して戻る。
+    fn wait_child(&mut self, block: bool) {
+        // WUNTRACED: 子プロセスの停止
+        // WNOHANG: ブロックしない
+        // WCONTINUED: 実行再開
+        let flag = if block {
+            Some(WaitPidFlag::WUNTRACED)
         } else {
-            // プロセスグループが空の場合、ジョブ情報を削除
+            Some(WaitPidFlag::WUNTRACED | WaitPidFlag::WNOHANG | WaitPidFlag::WCONTINUED)
+        };
+
+        loop {
+            match syscall(|| waitpid(Pid::from_raw(-1), flag)) {
+                // from_rawの引き数に-1を指定することで任意の子プロセスの状態変化を検知
+                Ok(WaitStatus::Exited(pid, status)) => {
+                    // プロセスが終了
+                    self.process_term(pid, ProcExit::Exited(status));
+                }
+                Ok(WaitStatus::Signaled(pid, sig, core)) => {
+                    // プロセスがシグナルにより終了
+                    self.process_term(pid, ProcExit::Signaled(sig, core));
+                }
+                // プロセスが停止
+                Ok(WaitStatus::Stopped(pid, _sig)) => self.process_stop(pid),
+                // プロセスが実行再開
+                Ok(WaitStatus::Continued(pid)) => self.process_continue(pid),
+                Ok(WaitStatus::StillAlive) => return, // waitすべき子プロセスはいない。
+                Err(nix::Error::ECHILD) => {
+                    // 子プロセスはいないので、フォアグラウンドのジョブを待つ必要もない
+                    if block {
+                        self.set_shell_fg();
+                    }
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("\nZeroSh: waitが失敗: {}", e);
+                    exit(1);
+                }
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                Ok(WaitStatus::PtraceEvent(pid, _, _) | WaitStatus::PtraceSyscall(pid)) => {
+                    self.process_stop(pid)
+                }
+            }
+
+            if block {
+                return;
+            }
+        }
+    }
+
+    /// プロセスの終了処理。
+    fn process_term(&mut self, pid: Pid, status: ProcExit) {
+        // パイプラインの段の終了状態を記録
+        if let Some(info) = self.pid_to_info.get(&pid) {
+            if let Some(stages) = self.pgid_to_status.get_mut(&info.pgid) {
+                stages[info.index] = status;
+            }
+            if self.fg != Some(info.pgid) {
+                // waitで取得できるように保存
+                self.finished_pids
+                    .push_back((pid, info.pgid, status.code()));
+                if self.finished_pids.len() > MAX_FINISHED_PIDS {
+                    self.finished_pids.pop_front();
+                }
+            }
+        }
+
+        // プロセスのIDを削除し、必要ならフォアグラウンドプロセスにシェルを設定
+        if let Some((job_id, pgid)) = self.remove_pid(pid) {
+            self.manage_job(job_id, pgid);
+        }
+    }
+
+    /// プロセスの停止処理。
+    fn process_stop(&mut self, pid: Pid) {
+        if self.set_pid_state(pid, ProcState::Stop).is_none() {
+            return; // 管理していないプロセス
+        }
+        let pgid = self.pid_to_info.get(&pid).unwrap().pgid; // プロセスグループIDを取得
+        let job_id = self.pgid_to_pids.get(&pgid).unwrap().0; // ジョブIDを取得
+        self.manage_job(job_id, pgid); // 必要ならフォアグラウンドプロセスをシェルに設定
+    }
+
+    /// プロセスの再開処理
+    fn process_continue(&mut self, pid: Pid) {
+        self.set_pid_state(pid, ProcState::Run);
+    }
+
+    ///　ジョブの管理。引数には変化のあったジョブとプロセスグループを指定。
+    ///
+    /// - フォアグラウンドプロセスが空の場合、シェルをフォアグラウンドに設定。
+    /// - フォアグラウンドプロセスが全て停止中の場合、シェルをフォアグラウンドに設定。
+    fn manage_job(&mut self, job_id: usize, pgid: Pid) {
+        let is_fg = self.fg == Some(pgid); // フォアグラウンドのプロセスか?
+        if is_fg {
+            // 状態が変化したプロセスはフォアグラウンドに設定
             if self.is_group_empty(pgid).unwrap() {
-                eprintln!("\n[{}] 終了\t{}", job_id, line);
+                // フォアグラウンドプロセスが空の場合、
+                // 終了コードを保存し、ジョブ情報を削除してシェルをフォアグラウンドに設定
+                let status = self.job_exit(pgid);
+                self.exit_val = status.code();
+                self.pipestatus = self.pgid_to_status[&pgid]
+                    .iter()
+                    .map(|s| s.code())
+                    .collect();
                 self.remove_job(job_id);
+                self.set_shell_fg();
+
+                if let ProcExit::Signaled(sig, _) = status {
+                    match sig {
+                        Signal::SIGINT => {
+                            // Ctrl+Cで中断された場合は、実行中のループやリストも中断
+                            self.interrupted = true;
+                            eprintln!();
+                        }
+                        Signal::SIGPIPE => (), // パイプラインでは普通に起きるため表示しない
+                        _ => eprintln!("{}", status.describe()),
+                    }
+                }
+            } else if self.is_group_stop(pgid).unwrap() {
+                // フォアグラウンドプロセスが全て停止中の場合、シェルをフォアグラウンドに設定
+                self.exit_val = 128 + SIGTSTP;
+                self.pipestatus = vec![self.exit_val; self.pgid_to_status[&pgid].len()];
+                self.touch_job(job_id);
+
+                // vimのように端末の設定を変更するジョブのため、fgで再開する時に戻せるよう保存
+                if self.job_control
+                    && let Ok(tmodes) = tcgetattr(libc::STDIN_FILENO)
+                {
+                    self.job_tmodes.insert(pgid, tmodes);
+                }
+                if self.job_control {
+                    eprintln!("\n{}", self.format_job(job_id, "Stopped"));
+                }
+                self.set_shell_fg();
             }
+        } else if self.is_group_empty(pgid).unwrap() {
+            // プロセスグループが空の場合、終了を通知してジョブ情報を削除
+            let status = self.job_exit(pgid);
+            self.notify_job(job_id, &status.describe());
+            self.finished_jobs.push_back((pgid, status.code())); // waitで取得できるように保存
+            self.remove_job(job_id);
+        } else if self.is_group_stop(pgid).unwrap() {
+            // 端末から読み込もうとした場合など、バックグラウンドのジョブが停止
+            self.touch_job(job_id);
+            self.notify_job(job_id, "Stopped");
+        }
+    }
+
+    /// バックグラウンドのジョブの状態変化を通知。
+    fn notify_job(&mut self, job_id: usize, state: &str) {
+        if self.job_control {
+            let msg = self.format_job(job_id, state);
+            let pgid = self.jobs[&job_id].0;
+            self.notifier.push(pgid, msg);
         }
     }
 
@@ -515,8 +2607,11 @@ impl Worker {
     fn insert_job(&mut self, job_id: usize, pgid: Pid, pids: HashMap<Pid, ProcInfo>, line: &str) {
         assert!(!self.jobs.contains_key(&job_id));
         self.jobs.insert(job_id, (pgid, line.to_string())); // ジョブ情報を追加
+        self.touch_job(job_id);
 
         let mut procs = HashSet::new(); // pgid_to_pidsへ追加するプロセス
+        self.pgid_to_status
+            .insert(pgid, vec![ProcExit::Exited(0); pids.len()]);
         for (pid, info) in pids {
             procs.insert(pid);
 
@@ -524,7 +2619,7 @@ impl Worker {
             self.pid_to_info.insert(pid, info); // プロセスの情報を追加
         }
 
-        assert!(!self.pid_to_info.contains_key(&pgid));
+        assert!(!self.pgid_to_pids.contains_key(&pgid));
         self.pgid_to_pids.insert(pgid, (job_id, procs)); // プロセスグループの情報を追加
     }
 
@@ -539,7 +2634,7 @@ impl Worker {
     /// (ジョブID、プロセスグループID)を返す。
     /// 存在しないプロセスの場合はNoneを返す。
     fn remove_pid(&mut self, pid: Pid) -> Option<(usize, Pid)> {
-        let pgid = self.pid_to_info.get(&pid)?.pgid; // プロセスグループIDを取得
+        let pgid = self.pid_to_info.remove(&pid)?.pgid; // プロセスの情報を削除し、プロセスグループIDを取得
         let it = self.pgid_to_pids.get_mut(&pgid)?;
         it.1.remove(&pid); // プロセスグループからpidを削除
         let job_id = it.0; // ジョブIDを取得
@@ -548,10 +2643,27 @@ impl Worker {
 
     /// ジョブ情報を削除し、関連するプロセスグループの情報も削除。
     fn remove_job(&mut self, job_id: usize) {
-        if let Some((pgid, _)) = self.jobs.remove(&job_id) {
-            if let Some((_, pids)) = self.pgid_to_pids.remove(&pgid) {
-                assert!(pids.is_empty()); // ジョブを削除する時はプロセスグループは空のはず
-            }
+        self.job_stack.retain(|id| *id != job_id);
+        if let Some((pgid, _)) = self.jobs.remove(&job_id)
+            && let Some((_, pids)) = self.pgid_to_pids.remove(&pgid)
+        {
+            self.pgid_to_status.remove(&pgid);
+            self.job_tmodes.remove(&pgid);
+            self.nohup_pgids.remove(&pgid);
+            assert!(pids.is_empty()); // ジョブを削除する時はプロセスグループは空のはず
+        }
+    }
+
+    /// ジョブの終了状態。パイプラインの最後の段の終了状態となる。
+    /// set -o pipefailの場合は、0以外で終了した最も右の段の終了状態となる。
+    fn job_exit(&self, pgid: Pid) -> ProcExit {
+        let Some(stages) = self.pgid_to_status.get(&pgid) else {
+            return ProcExit::Exited(0);
+        };
+        let failed = stages.iter().rev().find(|s| s.code() != 0);
+        match failed {
+            Some(s) if self.pipefail => *s,
+            _ => stages.last().copied().unwrap_or(ProcExit::Exited(0)),
         }
     }
 
@@ -571,90 +2683,517 @@ impl Worker {
     }
 
     /// シェルをフォアグラウンドに設定
-    fn set_shell_fg(&mut self, shell_tx: &SyncSender<ShellMsg>) {
+    ///
+    /// ジョブが端末の設定を変更したまま停止や終了した場合に備え、シェルの端末の設定も戻す。
+    fn set_shell_fg(&mut self) {
         self.fg = None; // fgの値が必要なければ単なる代入で良い。必要ならtake（）で取得。
-        tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid).unwrap();
-        shell_tx.send(ShellMsg::Continue(self.exit_val)).unwrap();
+        if self.job_control {
+            tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid).unwrap();
+            if let Some(tmodes) = &self.shell_tmodes {
+                let _ = tcsetattr(libc::STDIN_FILENO, SetArg::TCSADRAIN, tmodes);
+            }
+        }
     }
 
     /// 新たなジョブIDを取得。
     fn get_new_job_id(&self) -> Option<usize> {
-        for i in 0..=usize::MAX {
-            if !self.jobs.contains_key(&i) {
-                // jobに使われていない最小値を返す。
-                return Some(i);
+        // jobに使われていない最小値を返す。
+        (1..=usize::MAX).find(|i| !self.jobs.contains_key(i))
+    }
+}
+
+/// 適用するリダイレクト
+#[derive(Debug)]
+enum Redir {
+    Open {
+        fd: RawFd,    // 開いたファイルを割り当てるファイルディスクリプタ
+        path: String, // ファイル名
+        flags: OFlag, // open時のフラグ
+    },
+    Dup {
+        fd: RawFd,  // 複製先
+        src: RawFd, // 複製元
+    },
+    Close(RawFd), // n>&-
+    HereDoc {
+        fd: RawFd,    // 本文を読み込ませるファイルディスクリプタ
+        body: String, // ヒアドキュメント、ヒアストリングの内容
+    },
+}
+
+impl Redir {
+    /// 構文木のリダイレクトを、展開済みのリダイレクト先とヒアドキュメントの本文を使って変換。
+    /// &>は標準出力へのOpenと標準エラー出力へのDupの2つになる。
+    fn from_ast(r: &Redirect, target: String, body: String) -> Result<Vec<Redir>, DynError> {
+        let out_flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC;
+        let append_flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND;
+        let open = |default_fd, flags| Redir::Open {
+            fd: r.fd.unwrap_or(default_fd),
+            path: target.clone(),
+            flags,
+        };
+        let both = |flags| {
+            vec![
+                Redir::Open {
+                    fd: libc::STDOUT_FILENO,
+                    path: target.clone(),
+                    flags,
+                },
+                Redir::Dup {
+                    fd: libc::STDERR_FILENO,
+                    src: libc::STDOUT_FILENO,
+                },
+            ]
+        };
+
+        let redirs = match r.op {
+            RedirOp::In => vec![open(libc::STDIN_FILENO, OFlag::O_RDONLY)],
+            RedirOp::Out => vec![open(libc::STDOUT_FILENO, out_flags)],
+            RedirOp::Append => vec![open(libc::STDOUT_FILENO, append_flags)],
+            RedirOp::OutErr => both(out_flags),
+            RedirOp::AppendErr => both(append_flags),
+            RedirOp::HereDoc | RedirOp::HereDocStrip => vec![Redir::HereDoc {
+                fd: r.fd.unwrap_or(libc::STDIN_FILENO),
+                body,
+            }],
+            RedirOp::HereStr => vec![Redir::HereDoc {
+                fd: r.fd.unwrap_or(libc::STDIN_FILENO),
+                body: target + "\n",
+            }],
+            RedirOp::DupIn | RedirOp::DupOut => {
+                let default_fd = if r.op == RedirOp::DupIn {
+                    libc::STDIN_FILENO
+                } else {
+                    libc::STDOUT_FILENO
+                };
+                let fd = r.fd.unwrap_or(default_fd);
+                if target == "-" {
+                    vec![Redir::Close(fd)]
+                } else if let Ok(src) = target.parse::<RawFd>() {
+                    vec![Redir::Dup { fd, src }]
+                } else if r.op == RedirOp::DupOut && r.fd.is_none() {
+                    both(out_flags) // >&fileは&>fileと同じ
+                } else {
+                    return Err(format!("{}: ambiguous redirect", target).into());
+                }
             }
+        };
+        Ok(redirs)
+    }
+
+    /// リダイレクト先のファイルディスクリプタ。
+    fn fd(&self) -> RawFd {
+        match self {
+            Redir::Open { fd, .. } | Redir::Dup { fd, .. } | Redir::HereDoc { fd, .. } => *fd,
+            Redir::Close(fd) => *fd,
         }
-        None
     }
+
+    /// 現在のプロセスにリダイレクトを適用。
+    fn apply(&self) -> Result<(), String> {
+        match self {
+            Redir::Open { fd, path, flags } => {
+                let mode = Mode::from_bits_truncate(0o666);
+                let opened = syscall(|| open(path.as_str(), *flags, mode))
+                    .map_err(|e| format!("{}: {}", path, e.desc()))?;
+                if opened != *fd {
+                    syscall(|| dup2(opened, *fd)).map_err(|e| format!("{}: {}", path, e.desc()))?;
+                    syscall(|| unistd::close(opened)).unwrap();
+                }
+            }
+            Redir::Dup { fd, src } => {
+                if fd != src {
+                    syscall(|| dup2(*src, *fd)).map_err(|e| format!("{}: {}", src, e.desc()))?;
+                }
+            }
+            Redir::Close(fd) => {
+                let _ = syscall(|| unistd::close(*fd));
+            }
+            Redir::HereDoc { fd, body } => {
+                // 本文を書き込んだ一時ファイルを、削除してから読み込ませる
+                let err = |e: nix::Error| format!("here-document: {}", e.desc());
+                let mut tmpl = std::env::temp_dir();
+                tmpl.push("zerosh-heredoc-XXXXXX");
+                let (tmp, path) = unistd::mkstemp(&tmpl).map_err(err)?;
+                unistd::unlink(&path).map_err(err)?;
+
+                let mut buf = body.as_bytes();
+                while !buf.is_empty() {
+                    let n = syscall(|| unistd::write(tmp, buf)).map_err(err)?;
+                    buf = &buf[n..];
+                }
+                unistd::lseek(tmp, 0, unistd::Whence::SeekSet).map_err(err)?;
+
+                if tmp != *fd {
+                    syscall(|| dup2(tmp, *fd)).map_err(err)?;
+                    syscall(|| unistd::close(tmp)).unwrap();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 実行するコマンド
+#[derive(Debug)]
+struct ExecCmd {
+    args: Vec<String>,              // 先頭の要素はコマンド名
+    assigns: Vec<(String, String)>, // コマンド名の前に書かれた代入
+    redirs: Vec<Redir>,             // 先頭から順に適用するリダイレクト
+}
+
+/// "${A[@]}"のように、ダブルクォートの中でも要素ごとに別のフィールドとなるパラメータなら真。
+fn is_at_param(part: &WordPart) -> bool {
+    match part {
+        WordPart::Param(p) => p.name == "@" || p.index.as_deref() == Some("@"),
+        _ => false,
+    }
+}
+
+/// $@、$*、${A[@]}、${A[*]}のように、全ての要素を表すパラメータなら真。
+fn is_all_elements(p: &Param) -> bool {
+    matches!(p.name.as_str(), "@" | "*") || matches!(p.index.as_deref(), Some("@" | "*"))
+}
+
+/// ${V:offset:length}で、itemsの一部を切り出す。
+/// 負のoffsetは末尾から数え、負のlengthは末尾から数えた終了位置とする。
+fn substr<T: Clone>(items: &[T], offset: i64, length: Option<i64>) -> Result<Vec<T>, DynError> {
+    let len = items.len() as i64;
+
+    let mut start = offset;
+    if start < 0 {
+        start += len;
+    }
+    if start < 0 || start > len {
+        return Ok(Vec::new());
+    }
+
+    let end = match length {
+        Some(n) => {
+            let end = if n < 0 { len + n } else { start + n };
+            if end < start {
+                return Err(format!("{}: substring expression < 0", n).into());
+            }
+            end.min(len)
+        }
+        None => len,
+    };
+    Ok(items[start as usize..end as usize].to_vec())
+}
+
+/// ${V^}、${V^^}、${V,}、${V,,}の大文字、小文字の変換。
+/// allが偽なら先頭の1文字のみを変換する。
+fn convert_case(s: &str, upper: bool, all: bool) -> String {
+    let conv = |c: char| -> String {
+        if upper {
+            c.to_uppercase().collect()
+        } else {
+            c.to_lowercase().collect()
+        }
+    };
+
+    let mut chars = s.chars();
+    if all {
+        chars.map(conv).collect()
+    } else {
+        match chars.next() {
+            Some(c) => conv(c) + chars.as_str(),
+            None => String::new(),
+        }
+    }
+}
+
+/// setで表示する値を、再入力できるように必要ならシングルクォートで囲む。
+fn quote_value(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:,+@%=".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// ダブルクォートの中に書けるよう、特殊な文字をエスケープ。
+fn escape_dquote(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '$' | '`' | '"' | '\\') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// shoptで設定できるオプションの名前
+const SHOPT_NAMES: [&str; 6] = [
+    "dotglob",
+    "extglob",
+    "failglob",
+    "globstar",
+    "nocaseglob",
+    "nullglob",
+];
+
+/// 組み込みコマンドなら真。
+fn is_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "exit"
+            | "jobs"
+            | "fg"
+            | "bg"
+            | "kill"
+            | "wait"
+            | "disown"
+            | "set"
+            | "export"
+            | "readonly"
+            | "unset"
+            | "test"
+            | "["
+            | "cd"
+            | "let"
+            | "shopt"
+    )
 }
 
-type CmdResult<'a> = Result<Vec<(&'a str, Vec<&'a str>)>, DynError>;
+/// シグナル名、またはシグナル番号を解釈。SIGINT、INT、int、2のいずれも受け付ける。
+/// 0の場合は、シグナルを送らずにプロセスの存在だけを検査するためNoneを返す。
+fn parse_signal(s: &str) -> Result<Option<Signal>, String> {
+    let invalid = || format!("{}: 不正なシグナル指定です。", s);
+    if let Ok(n) = s.parse::<i32>() {
+        return match n {
+            0 => Ok(None),
+            _ => Signal::try_from(n).map(Some).map_err(|_| invalid()),
+        };
+    }
+
+    let upper = s.to_ascii_uppercase();
+    let name = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{}", upper)
+    };
+    name.parse::<Signal>().map(Some).map_err(|_| invalid())
+}
 
-/// コマンドをパース
-fn parse_cmd(line: &str) -> CmdResult {
-    let mut result = vec![];
+/// kill -lを実行。引数がなければシグナルの一覧を表示し、
+/// あれば番号を名前に、名前を番号に変換して表示する。
+fn list_signals(args: &[String]) -> i32 {
+    if args.is_empty() {
+        let names: Vec<String> = Signal::iterator()
+            .map(|sig| format!("{:2}) {:<12}", sig as i32, sig.as_str()))
+            .collect();
+        for row in names.chunks(5) {
+            println!("{}", row.concat().trim_end());
+        }
+        return 0;
+    }
 
-    for cmd in line.split('|').map(|s| s.trim()) {
-        let mut parts = cmd.split_whitespace();
-        if let Some(command) = parts.next() {
-            let args: Vec<&str> = parts.collect();
-            result.push((command, args));
+    let mut status = 0;
+    for arg in args {
+        if let Ok(n) = arg.parse::<i32>() {
+            // 128より大きい値はシグナルで終了した場合の終了コードとして扱う
+            let n = if n > 128 { n - 128 } else { n };
+            match Signal::try_from(n) {
+                Ok(sig) => println!("{}", &sig.as_str()[3..]),
+                Err(_) => {
+                    eprintln!("kill: {}: 不正なシグナル指定です。", arg);
+                    status = 1;
+                }
+            }
+        } else {
+            match parse_signal(arg) {
+                Ok(Some(sig)) => println!("{}", sig as i32),
+                _ => {
+                    eprintln!("kill: {}: 不正なシグナル指定です。", arg);
+                    status = 1;
+                }
+            }
         }
     }
+    status
+}
+
+/// シェルが変更したシグナルの動作をデフォルトに戻す。
+fn reset_signals() {
+    for sig in [
+        Signal::SIGINT,
+        Signal::SIGQUIT,
+        Signal::SIGHUP,
+        Signal::SIGTSTP,
+        Signal::SIGTTIN,
+        Signal::SIGTTOU,
+        Signal::SIGCHLD,
+        Signal::SIGPIPE,
+    ] {
+        unsafe { signal(sig, SigHandler::SigDfl).unwrap() };
+    }
+}
+
+/// シグナルの説明。bashがシグナルにより終了したジョブを表示する際の文字列。
+fn signal_desc(sig: Signal) -> &'static str {
+    match sig {
+        Signal::SIGHUP => "Hangup",
+        Signal::SIGINT => "Interrupt",
+        Signal::SIGQUIT => "Quit",
+        Signal::SIGILL => "Illegal instruction",
+        Signal::SIGTRAP => "Trace/breakpoint trap",
+        Signal::SIGABRT => "Aborted",
+        Signal::SIGBUS => "Bus error",
+        Signal::SIGFPE => "Floating point exception",
+        Signal::SIGKILL => "Killed",
+        Signal::SIGUSR1 => "User defined signal 1",
+        Signal::SIGSEGV => "Segmentation fault",
+        Signal::SIGUSR2 => "User defined signal 2",
+        Signal::SIGPIPE => "Broken pipe",
+        Signal::SIGALRM => "Alarm clock",
+        Signal::SIGTERM => "Terminated",
+        Signal::SIGXCPU => "CPU time limit exceeded",
+        Signal::SIGXFSZ => "File size limit exceeded",
+        Signal::SIGVTALRM => "Virtual timer expired",
+        Signal::SIGPROF => "Profiling timer expired",
+        Signal::SIGSYS => "Bad system call",
+        _ => sig.as_str(),
+    }
+}
+
+/// パイプの両端を閉じる。
+fn close_pipes(pipes: &[(RawFd, RawFd)]) {
+    for &(r, w) in pipes {
+        syscall(|| unistd::close(r)).unwrap();
+        syscall(|| unistd::close(w)).unwrap();
+    }
+}
+
+/// 子プロセスの標準入出力をinput、outputに設定し、不要なファイルディスクリプタを閉じる。
+fn set_stdio(input: Option<RawFd>, output: Option<RawFd>, pipes: &[(RawFd, RawFd)]) {
+    if let Some(infd) = input {
+        syscall(|| dup2(infd, libc::STDIN_FILENO)).unwrap();
+    }
+    if let Some(outfd) = output {
+        syscall(|| dup2(outfd, libc::STDOUT_FILENO)).unwrap();
+    }
+    close_pipes(pipes);
 
-    Ok(result)
+    // signal_hookで利用されるUnixドメインソケットとpipeをクローズ（標準入出力と標準エラー出力以外のファイルディスクプリタ）
+    for i in 3..=6 {
+        let _ = syscall(|| unistd::close(i));
+    }
 }
 
 /// プロセスグループIDを指定してfork & exec。
-/// pgidが0の場合は子プロセスのプロセスIDが、プロセスグループIDとなる。
+/// pgidがSome(0)の場合は子プロセスのプロセスIDが、プロセスグループIDとなる。
+/// Noneの場合はプロセスグループを変更しない。
 ///
 /// - inputがSome(fd)の場合は、標準入力をfdと設定。
 /// - outputがSome(fd)の場合は、標準出力をfdと設定。
+/// - pipesはパイプライン中の全パイプで、子プロセスでは標準入出力に複製した後に全て閉じる。
+/// - cmd.redirsはパイプの設定後に適用し、失敗した場合はexecせずに終了する。
+/// - envは子プロセスに渡す環境変数で、NAME=valueの形式。
+/// - #!のない実行可能なファイルは、/bin/shのスクリプトとして実行する。
+/// - 実行できない場合は、コマンドが見つからなければ127、それ以外は126で子プロセスを終了する。
 fn fork_exec(
-    pgid: Pid,
-    filename: &str,
-    args: &[&str],
-    input: Option<i32>,
-    output: Option<i32>,
+    pgid: Option<Pid>,
+    cmd: &ExecCmd,
+    path: Option<&str>,
+    env: &[CString],
+    input: Option<RawFd>,
+    output: Option<RawFd>,
+    pipes: &[(RawFd, RawFd)],
 ) -> Result<Pid, DynError> {
-    let filename = CString::new(filename).unwrap();
-    let args: Vec<CString> = args.iter().map(|&s| CString::new(s).unwrap()).collect();
+    // execvpeは子プロセスに渡す環境変数ではなくシェル自身の環境変数のPATHから検索するため、
+    // シェル変数のPATHを使って親プロセスで検索し、execveで実行する
+    let filename = find_command(&cmd.args[0], path);
+    let args: Vec<CString> = cmd
+        .args
+        .iter()
+        .map(|s| CString::new(s.as_str()).unwrap())
+        .collect();
+
+    // #!のないスクリプトを/bin/shで実行する場合の引数と、execに失敗した場合のメッセージの接頭辞
+    let sh_args: Vec<CString> = [CString::from(c"sh")]
+        .into_iter()
+        .chain(filename.clone())
+        .chain(args[1..].iter().cloned())
+        .collect();
+    let err_prefix = format!("ZeroSh: {}: ", cmd.args[0]);
 
     match syscall(|| unsafe { fork() })? {
         ForkResult::Parent { child, .. } => {
-            // 子プロセスのプロセスグループIDをpgidに設定
-            setpgid(child, pgid).unwrap();
+            // 子プロセスのプロセスグループIDをpgidに設定。
+            // 子プロセスが先に設定してexecしている場合は失敗するが、設定済みなので問題ない
+            if let Some(pgid) = pgid {
+                let _ = setpgid(child, pgid);
+            }
             Ok(child)
         }
         ForkResult::Child => {
-            setpgid(Pid::from_raw(0), pgid).unwrap();
-
-            // 標準入出力を設定
-            if let Some(infd) = input {
-                syscall(|| dup2(infd, libc::STDIN_FILENO)).unwrap();
-            }
-            if let Some(outfd) = output {
-                syscall(|| dup2(outfd, libc::STDOUT_FILENO)).unwrap();
+            if let Some(pgid) = pgid {
+                setpgid(Pid::from_raw(0), pgid).unwrap();
             }
 
-            // signal_hookで利用されるUnixドメインソケットとpipeをクローズ（標準入出力と標準エラー出力以外のファイルディスクプリタ）
-            for i in 3..=6 {
-                let _ = syscall(|| unistd::close(i));
-            }
+            // シェルが無視しているシグナルは、exec後も無視されたままになるため元に戻す
+            reset_signals();
 
-            // 実行ファイルをメモリに読み込み
-            match execvp(&filename, &args) {
-                Err(_) => {
-                    unistd::write(libc::STDERR_FILENO, "不明なコマンドを実行\n".as_bytes()).ok(); // ok(): Converts from Result<T, E> to Option<T>
+            // 標準入出力を設定
+            set_stdio(input, output, pipes);
+
+            // リダイレクトを適用
+            for redir in cmd.redirs.iter() {
+                if let Err(e) = redir.apply() {
+                    let msg = format!("ZeroSh: {}\n", e);
+                    unistd::write(libc::STDERR_FILENO, msg.as_bytes()).ok();
                     exit(1);
                 }
-                Ok(_) => unreachable!(), // このコードに到達しないことを明示。
             }
+
+            // 実行ファイルをメモリに読み込み。成功した場合は戻らない
+            let err = match &filename {
+                Some(filename) => match execve(filename, &args, env) {
+                    // 実行形式でないファイルは、シェルスクリプトとみなす
+                    Err(nix::Error::ENOEXEC) => execve(c"/bin/sh", &sh_args, env).unwrap_err(),
+                    result => result.unwrap_err(),
+                },
+                None => nix::Error::ENOENT, // PATHから見つからなかった
+            };
+
+            let (msg, code) = match err {
+                nix::Error::ENOENT => ("コマンドが見つかりません\n", 127),
+                nix::Error::EACCES => ("許可がありません\n", 126),
+                _ => ("実行できません\n", 126),
+            };
+            unistd::write(libc::STDERR_FILENO, err_prefix.as_bytes()).ok(); // ok(): Converts from Result<T, E> to Option<T>
+            unistd::write(libc::STDERR_FILENO, msg.as_bytes()).ok();
+            exit(code);
         }
     }
 }
 
+/// コマンド名に対応する実行ファイルのパス。名前に/を含む場合はそのまま使用し、
+/// 含まない場合はPATHのディレクトリから検索する。PATHが設定されていない場合や、見つからない場合はNone。
+fn find_command(name: &str, path: Option<&str>) -> Option<CString> {
+    if name.contains('/') {
+        return CString::new(name).ok();
+    }
+    if name.is_empty() {
+        return None;
+    }
+
+    path?
+        .split(':')
+        .map(|dir| {
+            let dir = if dir.is_empty() { "." } else { dir }; // 空の要素はカレントディレクトリ
+            format!("{}/{}", dir, name)
+        })
+        .find(|file| {
+            std::fs::metadata(file).is_ok_and(|m| m.is_file())
+                && access(file.as_str(), AccessFlags::X_OK).is_ok()
+        })
+        .and_then(|file| CString::new(file).ok())
+}
+
 /// ドロップ時にクロージャFを呼び出す型。
 struct CleanUp<F>
 where
@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, ffi::CString, fmt};
+
+/// シェル変数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Var {
+    pub value: Option<String>, // export NAMEのように値を設定せずに属性だけ付けた場合はNone
+    pub exported: bool,        // 子プロセスの環境変数として渡すなら真
+    pub readonly: bool,        // 読み取り専用なら真
+}
+
+/// 読み取り専用の変数を変更しようとした場合のエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadonlyError(pub String);
+
+impl fmt::Display for ReadonlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: 読み取り専用の変数です。", self.0)
+    }
+}
+
+impl std::error::Error for ReadonlyError {}
+
+/// シェル変数の一覧。名前順に保持する。
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    map: BTreeMap<String, Var>,
+}
+
+impl Vars {
+    /// シェルの起動時の環境変数を、エクスポートされた変数として取り込む。
+    pub fn from_env() -> Self {
+        let map = std::env::vars()
+            .map(|(name, value)| {
+                let var = Var {
+                    value: Some(value),
+                    exported: true,
+                    readonly: false,
+                };
+                (name, var)
+            })
+            .collect();
+        Vars { map }
+    }
+
+    /// 変数の値。設定されていない場合はNone。
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.map.get(name)?.value.as_deref()
+    }
+
+    /// 属性も含めた変数の情報。
+    pub fn get_var(&self, name: &str) -> Option<&Var> {
+        self.map.get(name)
+    }
+
+    /// 変数に値を代入。属性はそのまま引き継ぐ。
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), ReadonlyError> {
+        let var = self.map.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(ReadonlyError(name.to_string()));
+        }
+        var.value = Some(value);
+        Ok(())
+    }
+
+    /// 変数のエクスポート属性を設定、または解除。
+    pub fn export(&mut self, name: &str, on: bool) {
+        self.map.entry(name.to_string()).or_default().exported = on;
+    }
+
+    /// 変数を読み取り専用にする。
+    pub fn set_readonly(&mut self, name: &str) {
+        self.map.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    /// 変数を削除。
+    pub fn unset(&mut self, name: &str) -> Result<(), ReadonlyError> {
+        if self.map.get(name).is_some_and(|var| var.readonly) {
+            return Err(ReadonlyError(name.to_string()));
+        }
+        self.map.remove(name);
+        Ok(())
+    }
+
+    /// 一時的に変更した変数を、get_varで取得しておいた元の状態に戻す。
+    pub fn restore(&mut self, name: &str, var: Option<Var>) {
+        match var {
+            Some(var) => self.map.insert(name.to_string(), var),
+            None => self.map.remove(name),
+        };
+    }
+
+    /// 変数を名前順に列挙。
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Var)> {
+        self.map.iter()
+    }
+
+    /// 子プロセスに渡す環境変数。エクスポートされた変数に、
+    /// コマンドの前に書かれた一時的な代入を加えたもの。
+    pub fn environ(&self, assigns: &[(String, String)]) -> Vec<CString> {
+        let mut env: BTreeMap<&str, &str> = self
+            .map
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .filter_map(|(name, var)| Some((name.as_str(), var.value.as_deref()?)))
+            .collect();
+        for (name, value) in assigns.iter() {
+            env.insert(name, value);
+        }
+
+        env.into_iter()
+            .filter_map(|(name, value)| CString::new(format!("{}={}", name, value)).ok())
+            .collect()
+    }
+}